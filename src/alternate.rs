@@ -0,0 +1,77 @@
+// rules for finding a file's "related" file, e.g. a C header and its source. `{}` in `from`
+// captures the part of the path shared by both files and is substituted into `to`
+pub struct Rule {
+    pub from: String,
+    pub to: String,
+}
+
+impl Rule {
+    pub fn new(from: &str, to: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    fn apply(&self, path: &str) -> Option<String> {
+        let (prefix, suffix) = self.from.split_once("{}")?;
+        let rest = path.strip_suffix(suffix)?;
+
+        // the prefix has to start at a path component boundary
+        let start = match prefix {
+            "" => 0,
+            _ => rest
+                .match_indices(prefix)
+                .map(|(i, _)| i)
+                .filter(|i| *i == 0 || rest[..*i].ends_with('/'))
+                .last()?,
+        };
+        let name = &rest[start + prefix.len()..];
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(format!("{}{}", &rest[..start], self.to.replace("{}", name)))
+    }
+}
+
+pub fn default_rules() -> Vec<Rule> {
+    vec![
+        Rule::new("{}.h", "{}.c"),
+        Rule::new("{}.c", "{}.h"),
+        Rule::new("{}.hpp", "{}.cpp"),
+        Rule::new("{}.cpp", "{}.hpp"),
+        Rule::new("{}.h", "{}.cpp"),
+        Rule::new("{}.cpp", "{}.h"),
+        Rule::new("src/{}.rs", "tests/{}.rs"),
+        Rule::new("tests/{}.rs", "src/{}.rs"),
+        Rule::new("{}.ts", "{}.test.ts"),
+        Rule::new("{}.test.ts", "{}.ts"),
+    ]
+}
+
+// every alternate path the rules produce for `path`, in rule order
+pub fn candidates(rules: &[Rule], path: &str) -> Vec<String> {
+    rules
+        .iter()
+        .filter_map(|rule| rule.apply(path))
+        .filter(|candidate| candidate != path)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_match_at_path_component_boundaries() {
+        let rules = default_rules();
+
+        assert_eq!(candidates(&rules, "foo.h"), ["foo.c", "foo.cpp"]);
+        assert_eq!(
+            candidates(&rules, "crate/src/lib.rs"),
+            ["crate/tests/lib.rs"]
+        );
+        assert!(candidates(&rules, "mysrc/lib.rs").is_empty());
+    }
+}
@@ -1,20 +1,155 @@
+use std::{
+    fs::OpenOptions,
+    io::{Read, Write},
+};
+
+use anyhow::Context;
+
+use crate::{
+    filetype,
+    options::LocalOptions,
+    shell::Terminal,
+    text::{self, KeywordChars},
+};
+
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub lines: Vec<String>,
+}
+
+impl Edit {
+    // replaces the lines in `start..end` with `lines`
+    pub fn replace(start: usize, end: usize, lines: Vec<String>) -> Self {
+        Self { start, end, lines }
+    }
+
+    pub fn delete(start: usize, end: usize) -> Self {
+        Self::replace(start, end, vec![])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+// how many indented lines are sampled when guessing a file's indentation
+const INDENT_SAMPLE: usize = 100;
+
 pub struct Buffer {
     pub file: Option<String>,
     pub lines: Vec<String>,
+    pub indent: Option<Indent>,
+    pub options: LocalOptions,
+    // whether the last line ends with a newline, so saving doesn't add or drop one
+    pub eol: bool,
+    // what lines are joined with when saving, detected from the file
+    pub line_ending: LineEnding,
+    // whether there are changes that haven't been written to `file`
+    pub modified: bool,
+    pub terminal: Option<Terminal>,
 }
 
 impl Buffer {
-    pub fn from_file(file: Option<String>) -> Self {
-        let lines = match &file {
-            Some(file) => std::fs::read_to_string(file)
-                .unwrap()
-                .lines()
-                .map(|s| s.to_string())
-                .collect(),
-            None => vec![],
+    // a file that doesn't exist yet opens as an empty buffer that saves to it
+    pub fn from_file(file: Option<String>) -> anyhow::Result<Self> {
+        let contents = match &file {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err).with_context(|| format!("Can't open \"{path}\"")),
+            },
+            None => String::new(),
         };
 
-        Self { file, lines }
+        Ok(Self::with_contents(file, &contents))
+    }
+
+    pub fn with_contents(file: Option<String>, contents: &str) -> Self {
+        let (lines, eol) = split_lines(contents);
+        let line_ending = detect_line_ending(contents);
+        let indent = detect_indent(&lines);
+        let options = LocalOptions {
+            filetype: file
+                .as_deref()
+                .and_then(filetype::detect)
+                .map(|ft| ft.to_string()),
+            readonly: file
+                .as_deref()
+                .and_then(|file| std::fs::metadata(file).ok())
+                .map(|metadata| metadata.permissions().readonly()),
+            ..Default::default()
+        };
+
+        Self {
+            file,
+            lines,
+            indent,
+            options,
+            eol,
+            line_ending,
+            modified: false,
+            terminal: None,
+        }
+    }
+
+    // a buffer holding everything piped in, for `red -`. It has no file name yet, so it counts
+    // as modified until written somewhere
+    pub fn from_stdin() -> anyhow::Result<Self> {
+        Self::from_reader(std::io::stdin())
+    }
+
+    // reads all of `input` into an unnamed buffer, which starts out modified since nothing
+    // holds what it read
+    pub fn from_reader(mut input: impl Read) -> anyhow::Result<Self> {
+        let mut contents = String::new();
+        input
+            .read_to_string(&mut contents)
+            .context("Can't read from stdin")?;
+        Ok(Self {
+            modified: true,
+            ..Self::with_contents(None, &contents)
+        })
+    }
+
+    // a buffer showing the output of a shell
+    pub fn for_terminal(terminal: Terminal) -> Self {
+        Self {
+            lines: vec![String::new()],
+            terminal: Some(terminal),
+            ..Self::with_contents(None, "")
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match (&self.file, &self.terminal) {
+            (Some(file), _) => file,
+            (None, Some(_)) => "[Terminal]",
+            (None, None) => "No Name",
+        }
+    }
+
+    // borrows the line rather than copying it, for drawing
+    pub fn line(&self, line: usize) -> Option<&str> {
+        self.lines.get(line).map(|l| l.as_str())
     }
 
     pub fn get(&self, line: usize) -> Option<String> {
@@ -28,27 +163,736 @@ impl Buffer {
         self.lines.len()
     }
 
+    // adds empty lines until line `y` exists, so there's somewhere to type in an empty buffer
+    pub fn ensure_line(&mut self, y: usize) {
+        while self.lines.len() <= y {
+            self.lines.push(String::new());
+        }
+    }
+
+    // `x` is a column in characters, not bytes, so multi-byte characters are kept whole
     pub fn insert(&mut self, x: u16, y: usize, c: char) {
         if let Some(line) = self.lines.get_mut(y) {
-            (*line).insert(x as usize, c);
+            let at = text::byte_offset(line, x as usize);
+            line.insert(at, c);
+            self.modified = true;
         }
     }
 
     pub fn remove(&mut self, x: u16, y: usize) {
         if let Some(line) = self.lines.get_mut(y) {
-            (*line).remove(x as usize);
+            if let Some((at, _)) = line.char_indices().nth(x as usize) {
+                line.remove(at);
+                self.modified = true;
+            }
+        }
+    }
+
+    // overwrites the character at column `x`, returning the one it replaced
+    pub fn replace_char(&mut self, x: usize, y: usize, c: char) -> Option<char> {
+        let line = self.lines.get_mut(y)?;
+        let (at, original) = line.char_indices().nth(x)?;
+
+        line.replace_range(at..at + original.len_utf8(), c.encode_utf8(&mut [0; 4]));
+        self.modified = true;
+        Some(original)
+    }
+
+    // flips the case of the character at column `x`, returning whether there was one.
+    // Characters without case are left alone
+    pub fn toggle_case(&mut self, x: usize, y: usize) -> bool {
+        let Some(line) = self.lines.get_mut(y) else {
+            return false;
+        };
+        let Some((at, c)) = line.char_indices().nth(x) else {
+            return false;
+        };
+
+        let toggled: String = if c.is_lowercase() {
+            c.to_uppercase().collect()
+        } else if c.is_uppercase() {
+            c.to_lowercase().collect()
+        } else {
+            return true;
+        };
+        line.replace_range(at..at + c.len_utf8(), &toggled);
+        self.modified = true;
+
+        true
+    }
+
+    // adds one level of indentation to line `y`. Empty lines are left empty
+    pub fn indent_line(&mut self, y: usize, indent: &str) {
+        if let Some(line) = self.lines.get_mut(y).filter(|line| !line.is_empty()) {
+            line.insert_str(0, indent);
+            self.modified = true;
+        }
+    }
+
+    // removes up to `width` cells of leading whitespace from line `y`
+    pub fn dedent_line(&mut self, y: usize, width: usize, tabstop: usize) {
+        let Some(line) = self.lines.get_mut(y) else {
+            return;
+        };
+
+        let mut cells = 0;
+        let end = line
+            .char_indices()
+            .take_while(|(_, c)| {
+                let fits = cells < width && (*c == ' ' || *c == '\t');
+                cells += text::char_width(*c, cells, tabstop);
+                fits
+            })
+            .last()
+            .map_or(0, |(i, c)| i + c.len_utf8());
+
+        if end > 0 {
+            line.replace_range(..end, "");
+            self.modified = true;
+        }
+    }
+
+    // moves everything from column `x` on into a new line below `y`
+    pub fn split_line(&mut self, x: usize, y: usize) {
+        self.ensure_line(y);
+        let line = &mut self.lines[y];
+
+        let at = text::byte_offset(line, x);
+        let rest = line.split_off(at);
+        self.lines.insert(y + 1, rest);
+        self.modified = true;
+    }
+
+    // cuts line `y` off at column `x`, returning what was removed
+    pub fn truncate_line(&mut self, x: usize, y: usize) -> String {
+        let Some(line) = self.lines.get_mut(y) else {
+            return String::new();
+        };
+
+        let removed = line.split_off(text::byte_offset(line, x));
+        if !removed.is_empty() {
+            self.modified = true;
+        }
+        removed
+    }
+
+    // appends line `y + 1` onto the end of line `y`
+    pub fn join_line(&mut self, y: usize) {
+        if y + 1 < self.len() {
+            let next = self.lines.remove(y + 1);
+            self.lines[y].push_str(&next);
+            self.modified = true;
+        }
+    }
+
+    // where `w` lands from column `x` of line `y`: the next word on the line, or the first one
+    // on a following line. Empty lines count as words, like in vim
+    pub fn next_word_start(
+        &self,
+        x: usize,
+        y: usize,
+        iskeyword: &KeywordChars,
+    ) -> Option<(usize, usize)> {
+        if let Some(x) = text::next_word_start(self.lines.get(y)?, x, iskeyword) {
+            return Some((x, y));
+        }
+
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(y + 1)
+            .find_map(
+                |(y, line)| match line.chars().position(|c| !c.is_whitespace()) {
+                    Some(x) => Some((x, y)),
+                    None if line.is_empty() => Some((0, y)),
+                    None => None,
+                },
+            )
+    }
+
+    // where `b` lands from column `x` of line `y`: the previous word on the line, or the last
+    // one on an earlier line, skipping blank lines
+    pub fn prev_word_start(
+        &self,
+        x: usize,
+        y: usize,
+        iskeyword: &KeywordChars,
+    ) -> Option<(usize, usize)> {
+        if let Some(x) = text::prev_word_start(self.lines.get(y)?, x, iskeyword) {
+            return Some((x, y));
+        }
+
+        self.lines[..y]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(y, line)| {
+                text::prev_word_start(line, usize::MAX, iskeyword).map(|x| (x, y))
+            })
+    }
+
+    // where `e` lands from column `x` of line `y`: the end of the next word, moving on to
+    // following lines when the rest of this one is blank
+    pub fn word_end(&self, x: usize, y: usize, iskeyword: &KeywordChars) -> Option<(usize, usize)> {
+        if let Some(x) = text::word_end(self.lines.get(y)?, x + 1, iskeyword) {
+            return Some((x, y));
+        }
+
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(y + 1)
+            .find_map(|(y, line)| text::word_end(line, 0, iskeyword).map(|x| (x, y)))
+    }
+
+    // where `}` lands from line `y`: the next empty line after a paragraph, or the end of the
+    // last line. A run of empty lines counts as one boundary
+    pub fn next_paragraph(&self, y: usize) -> (usize, usize) {
+        let mut i = y + 1;
+        while i < self.len() && self.lines[i].is_empty() {
+            i += 1;
+        }
+        while i < self.len() && !self.lines[i].is_empty() {
+            i += 1;
+        }
+
+        match self.lines.get(i) {
+            Some(_) => (0, i),
+            None => {
+                let last = self.len().saturating_sub(1);
+                (self.lines.get(last).map_or(0, |l| l.chars().count()), last)
+            }
+        }
+    }
+
+    // where `{` lands from line `y`: the previous empty line before a paragraph, or the start of
+    // the first line
+    pub fn prev_paragraph(&self, y: usize) -> (usize, usize) {
+        let empty = |i: usize| self.lines.get(i).is_some_and(|line| line.is_empty());
+        let mut i = y.min(self.len().saturating_sub(1));
+        // off the boundary the cursor may be on, then back to the one before it
+        while i > 0 && empty(i) {
+            i -= 1;
+        }
+        while i > 0 && !empty(i) {
+            i -= 1;
+        }
+
+        (0, i)
+    }
+
+    // the span of the innermost `open`/`close` pair around (x, y), as (line, column) positions
+    // with the end exclusive. Inside a pair spread over lines, the lines in between are taken
+    // whole, leaving the lines with the delimiters
+    pub fn pair_object(
+        &self,
+        x: usize,
+        y: usize,
+        open: char,
+        close: char,
+        around: bool,
+    ) -> Option<((usize, usize), (usize, usize))> {
+        let (ox, oy) = self.enclosing_open(x, y, open, close)?;
+        let (cx, cy) = self.matching_bracket(ox, oy, &[(open, close)])?;
+        if around {
+            return Some(((oy, ox), (cy, cx + 1)));
+        }
+
+        let mut start = (oy, ox + 1);
+        let mut end = (cy, cx);
+        if cy > oy && start.1 == self.lines[oy].chars().count() {
+            start = (oy + 1, 0);
+        }
+        if cy > start.0 && self.lines[cy].chars().take(cx).all(char::is_whitespace) {
+            end = (cy, 0);
+        }
+
+        Some((start, end))
+    }
+
+    // the nearest `open` before (x, y) that isn't closed before it, or the one under the cursor
+    fn enclosing_open(
+        &self,
+        x: usize,
+        y: usize,
+        open: char,
+        close: char,
+    ) -> Option<(usize, usize)> {
+        let mut depth = 0;
+        for (ly, line) in self.lines.get(..=y)?.iter().enumerate().rev() {
+            let chars: Vec<char> = line.chars().collect();
+            let end = if ly == y {
+                (x + 1).min(chars.len())
+            } else {
+                chars.len()
+            };
+            for lx in (0..end).rev() {
+                if chars[lx] == open {
+                    if depth == 0 {
+                        return Some((lx, ly));
+                    }
+                    depth -= 1;
+                } else if chars[lx] == close && (lx, ly) != (x, y) {
+                    depth += 1;
+                }
+            }
+        }
+
+        None
+    }
+
+    // where `%` jumps from column `x` of line `y`: the partner of the first bracket at or after
+    // the cursor on the line, counting nested pairs across lines
+    pub fn matching_bracket(
+        &self,
+        x: usize,
+        y: usize,
+        pairs: &[(char, char)],
+    ) -> Option<(usize, usize)> {
+        let (x, (open, close), forward) =
+            self.lines
+                .get(y)?
+                .chars()
+                .enumerate()
+                .skip(x)
+                .find_map(|(x, c)| {
+                    pairs.iter().find_map(|&(open, close)| match c {
+                        c if c == open => Some((x, (open, close), true)),
+                        c if c == close => Some((x, (open, close), false)),
+                        _ => None,
+                    })
+                })?;
+
+        let mut depth = 0;
+        let mut visit = |c: char| {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+            }
+            depth == 0
+        };
+
+        if forward {
+            for (ly, line) in self.lines.iter().enumerate().skip(y) {
+                let start = if ly == y { x } else { 0 };
+                for (lx, c) in line.chars().enumerate().skip(start) {
+                    if visit(c) {
+                        return Some((lx, ly));
+                    }
+                }
+            }
+        } else {
+            for (ly, line) in self.lines[..=y].iter().enumerate().rev() {
+                let chars: Vec<char> = line.chars().collect();
+                let end = if ly == y { x + 1 } else { chars.len() };
+                for lx in (0..end).rev() {
+                    if visit(chars[lx]) {
+                        return Some((lx, ly));
+                    }
+                }
+            }
         }
+
+        None
     }
 
-    pub fn remove_line(&mut self, line: usize) {
-        if self.len() > line {
-            self.lines.remove(line);
+    // the next match of `query` after column `x` of line `y`, wrapping around past the end of
+    // the buffer
+    pub fn find_next(&self, query: &str, x: usize, y: usize) -> Option<(usize, usize)> {
+        if query.is_empty() || self.lines.is_empty() {
+            return None;
         }
+
+        (0..=self.len()).find_map(|i| {
+            let y = (y + i) % self.len();
+            let line = &self.lines[y];
+            let from = if i == 0 {
+                text::byte_offset(line, x + 1)
+            } else {
+                0
+            };
+            let at = from + line[from..].find(query)?;
+
+            Some((line[..at].chars().count(), y))
+        })
     }
 
-    pub(crate) fn insert_line(&mut self, y: usize, contents: String) {
-        if self.len() > y {
-            self.lines.insert(y, contents);
+    // the closest match of `query` before column `x` of line `y`, wrapping around past the
+    // start of the buffer
+    pub fn find_prev(&self, query: &str, x: usize, y: usize) -> Option<(usize, usize)> {
+        if query.is_empty() || self.lines.is_empty() {
+            return None;
         }
+
+        let len = self.len();
+        (0..=len).find_map(|i| {
+            let y = (y + len - i % len) % len;
+            let line = &self.lines[y];
+            let to = if i == 0 {
+                text::byte_offset(line, x)
+            } else {
+                line.len()
+            };
+            let at = line[..to].rfind(query)?;
+
+            Some((line[..at].chars().count(), y))
+        })
+    }
+
+    // writes the buffer back to the file it was read from
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let Some(file) = &self.file else {
+            anyhow::bail!("No file name");
+        };
+
+        self.write_to(file, 0..self.len(), false, self.eol)?;
+        self.modified = false;
+
+        Ok(())
+    }
+
+    // writes the given lines to `path`, appending to it instead of overwriting when asked, and
+    // returns how many lines were written. `eol` is whether the whole buffer ends in a newline
+    pub fn write_to(
+        &self,
+        path: &str,
+        lines: std::ops::Range<usize>,
+        append: bool,
+        eol: bool,
+    ) -> anyhow::Result<usize> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .with_context(|| format!("Can't open file \"{path}\" for writing"))?;
+
+        let lines = &self.lines[lines.start.min(self.len())..lines.end.min(self.len())];
+        let line_ending = self.line_ending.as_str();
+        let mut contents = lines.join(line_ending);
+        // only the whole buffer can be missing its last newline
+        let partial = lines.len() < self.len();
+        if (eol || partial) && !lines.is_empty() {
+            contents.push_str(line_ending);
+        }
+        file.write_all(contents.as_bytes())?;
+
+        Ok(lines.len())
+    }
+
+    // splices a whole block of lines in at once, so large pastes don't shift the lines below
+    // once per inserted line
+    pub fn insert_lines(&mut self, at: usize, lines: Vec<String>) {
+        let at = at.min(self.len());
+        self.lines.splice(at..at, lines);
+        self.modified = true;
+    }
+
+    // applies the edit as a single unit and returns the edit that reverts it
+    pub fn apply_edit(&mut self, edit: Edit) -> Edit {
+        let end = edit.end.min(self.len());
+        let start = edit.start.min(end);
+        let inserted = edit.lines.len();
+
+        let before = self.lines.splice(start..end, edit.lines).collect();
+        self.modified = true;
+
+        Edit::replace(start, start + inserted, before)
+    }
+}
+
+// splits on `\n` by hand rather than with `lines()`, which can't tell a file ending in a
+// blank line from one that just ends with a newline. Returns whether there was a final newline
+pub fn split_lines(contents: &str) -> (Vec<String>, bool) {
+    if contents.is_empty() {
+        return (vec![], true);
+    }
+
+    let eol = contents.ends_with('\n');
+    let contents = contents.strip_suffix('\n').unwrap_or(contents);
+    let lines = contents
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect();
+
+    (lines, eol)
+}
+
+// CRLF when most lines end with one, so a file with a few stray endings saves consistently
+pub fn detect_line_ending(contents: &str) -> LineEnding {
+    let lines = contents.matches('\n').count();
+    let crlf = contents.matches("\r\n").count();
+
+    if crlf * 2 > lines {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    }
+}
+
+// guesses whether the lines are mostly indented with tabs or spaces and, for spaces, the most
+// common step between indentation levels
+pub fn detect_indent(lines: &[String]) -> Option<Indent> {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    let mut steps = std::collections::HashMap::new();
+    let mut previous = 0;
+
+    for line in lines.iter().filter(|l| !l.trim().is_empty()) {
+        let width = match line.chars().next() {
+            Some('\t') => {
+                tabs += 1;
+                continue;
+            }
+            Some(' ') => line.chars().take_while(|c| *c == ' ').count(),
+            _ => 0,
+        };
+
+        if width > 0 {
+            spaces += 1;
+        }
+        if width != previous {
+            *steps.entry(width.abs_diff(previous)).or_insert(0) += 1;
+        }
+        previous = width;
+
+        if tabs + spaces >= INDENT_SAMPLE {
+            break;
+        }
+    }
+
+    if tabs == 0 && spaces == 0 {
+        return None;
+    }
+    if tabs > spaces {
+        return Some(Indent::Tabs);
+    }
+
+    steps
+        .into_iter()
+        .filter(|(step, _)| (1..=8).contains(step))
+        .max_by_key(|(step, count)| (*count, std::cmp::Reverse(*step)))
+        .map(|(step, _)| Indent::Spaces(step))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        Buffer::with_contents(None, text)
+    }
+
+    #[test]
+    fn apply_edit_returns_the_edit_that_reverts_it() {
+        let mut buffer = buffer("a\nb\nc\n");
+
+        let undo = buffer.apply_edit(Edit::replace(0, 2, vec!["x".to_string()]));
+        assert_eq!(buffer.lines, ["x", "c"]);
+        assert!(buffer.modified);
+
+        buffer.apply_edit(undo);
+        assert_eq!(buffer.lines, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn apply_edit_clamps_to_the_end_of_the_buffer() {
+        let mut buffer = buffer("a\n");
+
+        let undo = buffer.apply_edit(Edit::replace(5, 9, vec!["b".to_string()]));
+        assert_eq!(buffer.lines, ["a", "b"]);
+
+        buffer.apply_edit(undo);
+        assert_eq!(buffer.lines, ["a"]);
+    }
+
+    #[test]
+    fn inserting_a_large_block_at_the_top_keeps_the_old_lines_after_it() {
+        let mut buffer = buffer(&"old\n".repeat(50_000));
+
+        buffer.insert_lines(0, vec!["new".to_string(); 50_000]);
+        assert_eq!(buffer.len(), 100_000);
+        assert_eq!(
+            (&*buffer.lines[49_999], &*buffer.lines[50_000]),
+            ("new", "old")
+        );
+    }
+
+    // splicing 50k lines in at the top of a 50k-line buffer should be one move of the existing
+    // lines, not one per inserted line. Run with `cargo test -- --ignored` to see how long it takes
+    #[test]
+    #[ignore]
+    fn bench_inserting_a_large_block_at_the_top() {
+        let mut buffer = buffer(&"old\n".repeat(50_000));
+        let lines = vec!["new".to_string(); 50_000];
+
+        let start = std::time::Instant::now();
+        buffer.insert_lines(0, lines);
+        println!("inserting 50k lines took {:?}", start.elapsed());
+    }
+
+    // loads `contents` and writes the whole buffer back out, returning what was written
+    fn round_trip(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("red-buffer-{}-{name}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let buffer = buffer(contents);
+        buffer
+            .write_to(path, 0..usize::MAX, false, buffer.eol)
+            .unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        written
+    }
+
+    #[test]
+    fn trailing_newlines_survive_a_round_trip() {
+        for (name, contents) in [("none", "a\nb"), ("one", "a\nb\n"), ("two", "a\nb\n\n")] {
+            assert_eq!(round_trip(name, contents), contents);
+        }
+        assert_eq!(
+            split_lines("a\n\n"),
+            (vec!["a".to_string(), String::new()], true)
+        );
+    }
+
+    #[test]
+    fn insert_and_remove_count_columns_in_characters() {
+        let mut buffer = buffer("é日b");
+
+        buffer.insert(1, 0, 'x');
+        assert_eq!(buffer.lines[0], "éx日b");
+        buffer.insert(4, 0, '本');
+        assert_eq!(buffer.lines[0], "éx日b本");
+
+        buffer.remove(2, 0);
+        assert_eq!(buffer.lines[0], "éxb本");
+        buffer.remove(0, 0);
+        assert_eq!(buffer.lines[0], "xb本");
+        // past the end does nothing
+        buffer.remove(9, 0);
+        assert_eq!(buffer.lines[0], "xb本");
+    }
+
+    #[test]
+    fn next_word_start_moves_on_to_the_following_lines() {
+        let words = KeywordChars::default();
+        let buffer = buffer("foo\n   \n  bar\n\nbaz");
+
+        assert_eq!(buffer.next_word_start(0, 0, &words), Some((2, 2)));
+        // an empty line is a word of its own
+        assert_eq!(buffer.next_word_start(2, 2, &words), Some((0, 3)));
+        assert_eq!(buffer.next_word_start(0, 4, &words), None);
+    }
+
+    #[test]
+    fn prev_word_start_goes_back_over_blank_lines() {
+        let words = KeywordChars::default();
+        let buffer = buffer("foo bar\n\n   \n  baz");
+
+        assert_eq!(buffer.prev_word_start(2, 3, &words), Some((4, 0)));
+        assert_eq!(buffer.prev_word_start(0, 0, &words), None);
+    }
+
+    #[test]
+    fn word_end_moves_on_when_the_rest_of_the_line_is_blank() {
+        let words = KeywordChars::default();
+        let buffer = buffer("foo  \n\n  bar");
+
+        // already on a word end, so the next one
+        assert_eq!(buffer.word_end(2, 0, &words), Some((4, 2)));
+        assert_eq!(buffer.word_end(0, 0, &words), Some((2, 0)));
+        assert_eq!(buffer.word_end(4, 2, &words), None);
+    }
+
+    #[test]
+    fn piped_input_fills_an_unnamed_modified_buffer() {
+        let buffer = Buffer::from_reader("one\ntwo\n".as_bytes()).unwrap();
+
+        assert_eq!(buffer.lines, ["one", "two"]);
+        assert_eq!(buffer.file, None);
+        assert!(buffer.modified);
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("red-buffer-{}-{name}", std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn missing_and_empty_files_open_as_empty_buffers() {
+        let missing = temp_path("missing");
+        let buffer = Buffer::from_file(Some(missing.clone())).unwrap();
+        assert!(buffer.lines.is_empty());
+        assert_eq!(buffer.file, Some(missing));
+        assert!(!buffer.modified);
+
+        let empty = temp_path("empty");
+        std::fs::write(&empty, "").unwrap();
+        let buffer = Buffer::from_file(Some(empty.clone())).unwrap();
+        std::fs::remove_file(&empty).unwrap();
+        assert!(buffer.lines.is_empty());
+
+        // anything else that stops it being read is an error, not an empty buffer
+        let dir = std::env::temp_dir().to_str().unwrap().to_string();
+        assert!(Buffer::from_file(Some(dir)).is_err());
+    }
+
+    #[test]
+    fn crlf_files_keep_their_line_endings() {
+        assert_eq!(round_trip("crlf", "a\r\nb\r\n"), "a\r\nb\r\n");
+        // mixed endings all become the one most lines use
+        assert_eq!(round_trip("mixed", "a\r\nb\r\nc\n"), "a\r\nb\r\nc\r\n");
+        assert_eq!(round_trip("lf", "a\nb\r\nc\n"), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn pair_object_picks_the_innermost_enclosing_pair() {
+        let buffer = buffer("f(a, (b), c)");
+
+        assert_eq!(
+            buffer.pair_object(6, 0, '(', ')', false),
+            Some(((0, 6), (0, 7)))
+        );
+        assert_eq!(
+            buffer.pair_object(6, 0, '(', ')', true),
+            Some(((0, 5), (0, 8)))
+        );
+        // on a closing bracket, it's that bracket's pair
+        assert_eq!(
+            buffer.pair_object(7, 0, '(', ')', false),
+            Some(((0, 6), (0, 7)))
+        );
+        assert_eq!(
+            buffer.pair_object(3, 0, '(', ')', false),
+            Some(((0, 2), (0, 11)))
+        );
+        assert_eq!(buffer.pair_object(0, 0, '(', ')', false), None);
+
+        let buffer = self::buffer("x[1][2] {y}");
+        assert_eq!(
+            buffer.pair_object(5, 0, '[', ']', false),
+            Some(((0, 5), (0, 6)))
+        );
+        assert_eq!(
+            buffer.pair_object(9, 0, '{', '}', true),
+            Some(((0, 8), (0, 11)))
+        );
+        assert_eq!(buffer.pair_object(9, 0, '(', ')', false), None);
+    }
+
+    #[test]
+    fn pair_object_over_lines_keeps_the_delimiter_lines() {
+        let buffer = buffer("fn f() {\n    a;\n    b;\n}\n");
+
+        assert_eq!(
+            buffer.pair_object(4, 1, '{', '}', false),
+            Some(((1, 0), (3, 0)))
+        );
+        assert_eq!(
+            buffer.pair_object(4, 2, '{', '}', true),
+            Some(((0, 7), (3, 1)))
+        );
     }
 }
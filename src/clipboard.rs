@@ -0,0 +1,83 @@
+// the system clipboard, used for the `+` register. Rather than linking against each platform's
+// clipboard API, it goes through whichever of the usual copy and paste commands is installed
+
+#[cfg(feature = "clipboard")]
+pub use system::{get, set};
+
+#[cfg(feature = "clipboard")]
+mod system {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    // (copy, paste) commands, tried in order until one runs
+    const COMMANDS: &[(&[&str], &[&str])] = &[
+        (&["pbcopy"], &["pbpaste"]),
+        (&["wl-copy"], &["wl-paste", "--no-newline"]),
+        (
+            &["xclip", "-selection", "clipboard"],
+            &["xclip", "-selection", "clipboard", "-o"],
+        ),
+        (
+            &["xsel", "--clipboard", "--input"],
+            &["xsel", "--clipboard", "--output"],
+        ),
+    ];
+
+    pub fn set(text: &str) -> anyhow::Result<()> {
+        for (copy, _) in COMMANDS {
+            let Ok(mut child) = Command::new(copy[0])
+                .args(&copy[1..])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+            else {
+                continue;
+            };
+
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(text.as_bytes())?;
+            }
+            if child.wait()?.success() {
+                return Ok(());
+            }
+        }
+
+        anyhow::bail!("Clipboard is not available")
+    }
+
+    pub fn get() -> anyhow::Result<String> {
+        for (_, paste) in COMMANDS {
+            let Ok(output) = Command::new(paste[0])
+                .args(&paste[1..])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+            else {
+                continue;
+            };
+
+            if output.status.success() {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+            }
+        }
+
+        anyhow::bail!("Clipboard is not available")
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub use stub::{get, set};
+
+#[cfg(not(feature = "clipboard"))]
+mod stub {
+    pub fn set(_text: &str) -> anyhow::Result<()> {
+        anyhow::bail!("Built without clipboard support, enable the `clipboard` feature")
+    }
+
+    pub fn get() -> anyhow::Result<String> {
+        anyhow::bail!("Built without clipboard support, enable the `clipboard` feature")
+    }
+}
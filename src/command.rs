@@ -0,0 +1,312 @@
+use anyhow::bail;
+
+use crate::text::Alignment;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Address {
+    Current,
+    Last,
+    // the line of a mark, like `'a` or `'<` for the start of the last visual selection
+    Mark(char),
+    // 1-based, as typed
+    Line(usize),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Range {
+    pub start: Address,
+    pub end: Address,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // `force` writes even with 'readonly' set
+    Write {
+        range: Option<Range>,
+        path: Option<String>,
+        append: bool,
+        force: bool,
+    },
+    Quit {
+        force: bool,
+    },
+    // `:x` only writes when there are changes. `force` also quits with other buffers modified
+    WriteQuit {
+        path: Option<String>,
+        force: bool,
+        if_modified: bool,
+    },
+    // without a path, reloads the current file
+    Edit {
+        path: Option<String>,
+        force: bool,
+    },
+    GoToLine(Address),
+    Alternate,
+    AlternateBuffer,
+    NextBuffer,
+    PrevBuffer,
+    ListBuffers,
+    Set(Vec<String>),
+    SetLocal(Vec<String>),
+    Pwd,
+    Find(String),
+    // reads diagnostics for the listed buffers from a file of compiler output
+    LoadDiagnostics(String),
+    Terminal(Option<String>),
+    Cd(Option<String>),
+    Align {
+        range: Option<Range>,
+        alignment: Alignment,
+        width: Option<usize>,
+    },
+    // `pattern` is matched literally. Without `global`, only the first match on each line
+    // is replaced
+    Substitute {
+        range: Option<Range>,
+        pattern: String,
+        replacement: String,
+        global: bool,
+    },
+}
+
+pub fn parse(input: &str) -> anyhow::Result<Command> {
+    let (range, input) = parse_range(input.trim())?;
+    let (name, args) = match input.find(|c: char| !c.is_alphabetic()) {
+        Some(i) => (&input[..i], input[i..].trim()),
+        None => (input, ""),
+    };
+
+    let command = match name {
+        "w" | "write" => {
+            let (force, args) = split_force(args);
+            let (append, path) = match args.strip_prefix(">>") {
+                Some(path) => (true, path.trim()),
+                None => (false, args),
+            };
+
+            if append && path.is_empty() {
+                bail!("Argument required");
+            }
+
+            Command::Write {
+                range,
+                path: (!path.is_empty()).then(|| path.to_string()),
+                append,
+                force,
+            }
+        }
+        "q" | "quit" => Command::Quit {
+            force: match args {
+                "" => false,
+                "!" => true,
+                _ => bail!("Trailing characters: {args}"),
+            },
+        },
+        "wq" | "x" | "xit" => {
+            let (force, path) = split_force(args);
+            Command::WriteQuit {
+                path: (!path.is_empty()).then(|| path.to_string()),
+                force,
+                if_modified: name != "wq",
+            }
+        }
+        "e" | "edit" => {
+            let (force, path) = split_force(args);
+
+            Command::Edit {
+                path: (!path.is_empty()).then(|| path.to_string()),
+                force,
+            }
+        }
+        // a range on its own, like `:42` or `:$`, jumps to its last line
+        "" if args.is_empty() => match range {
+            Some(range) => Command::GoToLine(range.end),
+            None => bail!("Not an editor command: {input}"),
+        },
+        "A" => Command::Alternate,
+        "pw" | "pwd" => Command::Pwd,
+        "fin" | "find" => Command::Find(args.to_string()),
+        "cf" | "cfile" => match args {
+            "" => bail!("Argument required"),
+            path => Command::LoadDiagnostics(path.to_string()),
+        },
+        "ter" | "terminal" => Command::Terminal((!args.is_empty()).then(|| args.to_string())),
+        "cd" => Command::Cd((!args.is_empty()).then(|| args.to_string())),
+        "set" | "se" => Command::Set(args.split_whitespace().map(|s| s.to_string()).collect()),
+        "setlocal" | "setl" => {
+            Command::SetLocal(args.split_whitespace().map(|s| s.to_string()).collect())
+        }
+        "bn" | "bnext" => Command::NextBuffer,
+        "bp" | "bprevious" | "bN" | "bNext" => Command::PrevBuffer,
+        "ls" | "buffers" | "files" => Command::ListBuffers,
+        "b" | "buffer" => match args {
+            "#" => Command::AlternateBuffer,
+            _ => bail!("Invalid buffer: {args}"),
+        },
+        "ce" | "center" | "le" | "left" | "ri" | "right" => Command::Align {
+            range,
+            alignment: match name {
+                "ce" | "center" => Alignment::Center,
+                "le" | "left" => Alignment::Left,
+                _ => Alignment::Right,
+            },
+            width: match args {
+                "" => None,
+                args => Some(parse_number(args)?),
+            },
+        },
+        "s" | "substitute" => {
+            let (pattern, replacement, global) = parse_substitute(args)?;
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                global,
+            }
+        }
+        _ => bail!("Not an editor command: {input}"),
+    };
+
+    if range.is_some()
+        && !matches!(
+            command,
+            Command::Align { .. }
+                | Command::GoToLine(_)
+                | Command::Substitute { .. }
+                | Command::Write { .. }
+        )
+    {
+        bail!("No range allowed");
+    }
+
+    Ok(command)
+}
+
+// splits the `!` off arguments like `! file`, for commands that it forces
+fn split_force(args: &str) -> (bool, &str) {
+    match args.strip_prefix('!') {
+        Some(args) => (true, args.trim()),
+        None => (false, args),
+    }
+}
+
+fn parse_number(arg: &str) -> anyhow::Result<usize> {
+    arg.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid argument: {arg}"))
+}
+
+// splits `/pattern/replacement/flags` into its parts. Any character can stand in for `/`, and
+// the closing one can be left off
+fn parse_substitute(args: &str) -> anyhow::Result<(String, String, bool)> {
+    let mut chars = args.chars();
+    let Some(delimiter) = chars.next() else {
+        bail!("Argument required");
+    };
+    if delimiter.is_alphanumeric() || delimiter == '\\' || delimiter == '"' {
+        bail!("Regular expressions can't be delimited by letters");
+    }
+
+    let mut parts = chars.as_str().splitn(3, delimiter);
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    if pattern.is_empty() {
+        bail!("No previous regular expression");
+    }
+
+    let mut global = false;
+    for flag in flags.chars() {
+        match flag {
+            'g' => global = true,
+            _ => bail!("Trailing characters: {flags}"),
+        }
+    }
+
+    Ok((pattern.to_string(), replacement.to_string(), global))
+}
+
+// splits a leading line range like `%`, `.`, `$`, `12` or `3,$` off the command
+fn parse_range(input: &str) -> anyhow::Result<(Option<Range>, &str)> {
+    if let Some(rest) = input.strip_prefix('%') {
+        let range = Range {
+            start: Address::Line(1),
+            end: Address::Last,
+        };
+        return Ok((Some(range), rest));
+    }
+
+    let Some((start, rest)) = parse_address(input)? else {
+        return Ok((None, input));
+    };
+
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => match parse_address(rest)? {
+            Some((end, rest)) => (end, rest),
+            None => bail!("Invalid range"),
+        },
+        None => (start, rest),
+    };
+
+    Ok((Some(Range { start, end }), rest))
+}
+
+fn parse_address(input: &str) -> anyhow::Result<Option<(Address, &str)>> {
+    if let Some(rest) = input.strip_prefix('.') {
+        return Ok(Some((Address::Current, rest)));
+    }
+    if let Some(rest) = input.strip_prefix('$') {
+        return Ok(Some((Address::Last, rest)));
+    }
+    if let Some(rest) = input.strip_prefix('\'') {
+        let mut chars = rest.chars();
+        return match chars.next() {
+            Some(c) => Ok(Some((Address::Mark(c), chars.as_str()))),
+            None => bail!("Invalid range"),
+        };
+    }
+
+    let digits = input.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 {
+        return Ok(None);
+    }
+
+    let line = input[..digits].parse()?;
+    Ok(Some((Address::Line(line), &input[digits..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_quit_commands_take_a_bang_and_a_file() {
+        assert_eq!(
+            parse("w!").unwrap(),
+            Command::Write {
+                range: None,
+                path: None,
+                append: false,
+                force: true,
+            }
+        );
+        assert_eq!(
+            parse("wq out.txt").unwrap(),
+            Command::WriteQuit {
+                path: Some("out.txt".to_string()),
+                force: false,
+                if_modified: false,
+            }
+        );
+        assert_eq!(
+            parse("x!").unwrap(),
+            Command::WriteQuit {
+                path: None,
+                force: true,
+                if_modified: true,
+            }
+        );
+        assert!(parse("q foo").is_err());
+        assert!(parse("w >>").is_err());
+    }
+}
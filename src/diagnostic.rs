@@ -0,0 +1,174 @@
+use crossterm::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    // plain ASCII, so the sign column lines up without a patched font
+    pub fn icon(&self) -> char {
+        match self {
+            Severity::Error => 'x',
+            Severity::Warning => '!',
+            Severity::Info => 'i',
+            Severity::Hint => '*',
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Severity::Error => Color::Rgb {
+                r: 243,
+                g: 139,
+                b: 168,
+            },
+            Severity::Warning => Color::Rgb {
+                r: 249,
+                g: 226,
+                b: 175,
+            },
+            Severity::Info => Color::Rgb {
+                r: 137,
+                g: 180,
+                b: 250,
+            },
+            Severity::Hint => Color::Rgb {
+                r: 148,
+                g: 226,
+                b: 213,
+            },
+        }
+    }
+
+    pub fn abbreviation(&self) -> char {
+        match self {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+            Severity::Info => 'I',
+            Severity::Hint => 'H',
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub col: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+// reads compiler output in the `file:line:col: message` form printed by `gcc` or `cargo build
+// --message-format short`, where the column is optional. The severity comes from how the
+// message starts, defaulting to an error, and lines in any other form are skipped
+pub fn parse_errors(output: &str) -> Vec<(String, Diagnostic)> {
+    output.lines().filter_map(parse_error).collect()
+}
+
+fn parse_error(row: &str) -> Option<(String, Diagnostic)> {
+    let (file, rest) = row.split_once(':')?;
+    let (line, rest) = rest.split_once(':')?;
+    let line = line.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let (col, message) = match rest.split_once(':') {
+        Some((col, message)) => match col.trim().parse::<usize>() {
+            Ok(col) => (col.saturating_sub(1), message),
+            Err(_) => (0, rest),
+        },
+        None => (0, rest),
+    };
+    if file.is_empty() {
+        return None;
+    }
+
+    let message = message.trim();
+    let lowercase = message.to_lowercase();
+    let severity = if lowercase.starts_with("warning") {
+        Severity::Warning
+    } else if lowercase.starts_with("note") || lowercase.starts_with("info") {
+        Severity::Info
+    } else if lowercase.starts_with("help") || lowercase.starts_with("hint") {
+        Severity::Hint
+    } else {
+        Severity::Error
+    };
+
+    let diagnostic = Diagnostic {
+        line,
+        col,
+        severity,
+        message: message.to_string(),
+    };
+    Some((file.to_string(), diagnostic))
+}
+
+// the most severe diagnostic on the given line, which is the one shown in the gutter
+pub fn for_line(diagnostics: &[Diagnostic], line: usize) -> Option<&Diagnostic> {
+    diagnostics
+        .iter()
+        .filter(|d| d.line == line)
+        .min_by_key(|d| d.severity)
+}
+
+// e.g. "2E 3W", skipping severities without diagnostics
+pub fn summary(diagnostics: &[Diagnostic]) -> String {
+    [
+        Severity::Error,
+        Severity::Warning,
+        Severity::Info,
+        Severity::Hint,
+    ]
+    .iter()
+    .filter_map(|severity| {
+        let count = diagnostics
+            .iter()
+            .filter(|d| d.severity == *severity)
+            .count();
+        (count > 0).then(|| format!("{count}{}", severity.abbreviation()))
+    })
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiler_output_is_read_with_and_without_columns() {
+        let output = "src/main.rs:3:5: error[E0425]: cannot find value `x`\n\
+                      Compiling red\n\
+                      lib.c:10: warning: unused variable\n";
+
+        let found = parse_errors(output);
+        assert_eq!(found.len(), 2);
+        let (file, d) = &found[0];
+        assert_eq!(file, "src/main.rs");
+        assert_eq!((d.line, d.col, d.severity), (2, 4, Severity::Error));
+        assert_eq!(d.message, "error[E0425]: cannot find value `x`");
+        let (file, d) = &found[1];
+        assert_eq!(file, "lib.c");
+        assert_eq!((d.line, d.col, d.severity), (9, 0, Severity::Warning));
+    }
+
+    #[test]
+    fn summary_counts_by_severity() {
+        let diagnostic = |severity| Diagnostic {
+            line: 0,
+            col: 0,
+            severity,
+            message: String::new(),
+        };
+        let diagnostics = [
+            diagnostic(Severity::Warning),
+            diagnostic(Severity::Error),
+            diagnostic(Severity::Warning),
+        ];
+
+        assert_eq!(summary(&diagnostics), "1E 2W");
+        assert_eq!(for_line(&diagnostics, 0).unwrap().severity, Severity::Error);
+    }
+}
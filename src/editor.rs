@@ -1,18 +1,43 @@
-use std::io::{stdout, Write};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{stdout, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use crossterm::{
     cursor,
     event::{self, read, KeyModifiers},
     style::{self, Color, Stylize},
-    terminal, ExecutableCommand, QueueableCommand,
+    terminal, Command as _, ExecutableCommand, QueueableCommand,
 };
 
-use crate::{buffer::Buffer, log};
+use crate::{
+    alternate::{self, Rule},
+    buffer::{Buffer, Edit, Indent, LineEnding},
+    clipboard,
+    command::{self, Address, Command, Range},
+    diagnostic::{self, Diagnostic},
+    finder::Finder,
+    keymap::{self, Key, Keymap, Lookup},
+    options::{LocalOptions, Options},
+    register::Register,
+    shell::Terminal,
+    text::{self, Alignment, FindKind, TextObject},
+    undofile,
+};
 
+#[derive(Clone)]
 enum Action {
     Undo,
-    Quit,
+    Redo,
+    Save,
+    StartRecording(char),
+    StopRecording,
+    // `@` replays the last macro played
+    PlayMacro(char),
 
     MoveUp,
     MoveDown,
@@ -22,29 +47,499 @@ enum Action {
     MoveToLineStart,
     MoveToLineEnd,
 
+    MoveWordForward,
+    MoveWordBackward,
+    MoveWordEnd,
+    FindChar(char, FindKind),
+    RepeatFind,
+    RepeatFindReverse,
+    MatchBracket,
+    MoveParagraphForward,
+    MoveParagraphBackward,
+
     PageUp,
     PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    ScrollUp(usize),
+    ScrollDown(usize),
+    // a click, as (column, row) on screen
+    MoveToScreenPosition(u16, u16),
 
     InsertCharAtCursorPos(char),
+    InsertTextAtCursorPos(String),
+    InsertTab,
     DeleteCharAtCursorPos,
+    DeleteCharBeforeCursor,
     NewLine,
+    AppendAfterCursor,
+    AppendAtLineEnd,
+    InsertAtFirstNonBlank,
+    OpenLineBelow,
+    OpenLineAbove,
+    DeleteToLineEnd,
+    ReplaceChar(char),
+    ToggleCase,
+    IndentLines,
+    DedentLines,
+    OverwriteChar(char),
+    RestoreOverwrittenChar,
+    ChangeToLineEnd,
+    ChangeLine,
 
     EnterMode(Mode),
     SetWaitingCmd(char),
     DeleteCurrentLine,
-    InsertLineAt(usize, Option<String>),
+    // `d` followed by a motion, deleting the text the motion moves over
+    DeleteMotion(Box<Action>),
+    // `c` followed by a motion, deleting like `d` and then inserting
+    ChangeMotion(Box<Action>),
+    // `d` or `c` followed by `i` or `a`, waiting for the key naming the text object
+    AwaitTextObject(char, bool),
+    DeleteObject(TextObject),
+    ChangeObject(TextObject),
+    SelectRegister(char),
+    YankLine,
+    PasteAfter,
+    PasteBefore,
+    RepeatChange,
+    ApplyEdit(Edit),
     MoveLineToViewportCenter,
+    MoveLineToViewportTop,
+    MoveLineToViewportBottom,
+    NextDiagnostic,
+    PrevDiagnostic,
+
+    SwitchToAlternateBuffer,
+    ShowFileInfo,
+
+    InsertCommandChar(char),
+    DeleteCommandChar,
+    ExecuteCommand,
+
+    SendToTerminal(Vec<u8>),
+
+    SearchNext,
+    SearchPrev,
+    GoToLine(usize),
+    GoToFirstLine,
+    GoToLastLine,
+    SetMark(char),
+    JumpBack,
+    JumpForward,
+    // to the mark's exact position with `` ` ``, or the first non-blank of its line with `'`
+    JumpToMark(char),
+    JumpToMarkLine(char),
+
+    DeleteSelection,
+    YankSelection,
+    // `I` or `A` in visual block mode, typing text that goes on every line of the block
+    BlockInsert(bool),
+    // `:` in visual mode, starting a command on the selected lines
+    CommandOnSelection,
+
+    OpenFinder,
+    FinderInsertChar(char),
+    FinderDeleteChar,
+    FinderSelectNext,
+    FinderSelectPrev,
+    FinderOpenSelection,
+}
+
+impl Action {
+    // informational messages only last until the cursor moves or the mode changes
+    fn clears_message(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveToLineStart
+                | Action::MoveToLineEnd
+                | Action::MoveWordForward
+                | Action::MoveWordBackward
+                | Action::MoveWordEnd
+                | Action::FindChar(_, _)
+                | Action::RepeatFind
+                | Action::RepeatFindReverse
+                | Action::MatchBracket
+                | Action::MoveParagraphForward
+                | Action::MoveParagraphBackward
+                | Action::PageUp
+                | Action::PageDown
+                | Action::HalfPageUp
+                | Action::HalfPageDown
+                | Action::ScrollUp(_)
+                | Action::ScrollDown(_)
+                | Action::MoveToScreenPosition(_, _)
+                | Action::MoveLineToViewportCenter
+                | Action::MoveLineToViewportTop
+                | Action::MoveLineToViewportBottom
+                | Action::GoToFirstLine
+                | Action::GoToLastLine
+                | Action::JumpToMark(_)
+                | Action::JumpToMarkLine(_)
+                | Action::JumpBack
+                | Action::JumpForward
+                | Action::NextDiagnostic
+                | Action::PrevDiagnostic
+                | Action::EnterMode(_)
+        )
+    }
+
+    // changes made from normal mode that `.` repeats. Those that start inserting also take the
+    // text typed before leaving insert mode
+    fn is_repeatable_change(&self) -> bool {
+        matches!(
+            self,
+            Action::DeleteCharAtCursorPos
+                | Action::DeleteCurrentLine
+                | Action::DeleteMotion(_)
+                | Action::ChangeMotion(_)
+                | Action::DeleteObject(_)
+                | Action::ChangeObject(_)
+                | Action::DeleteToLineEnd
+                | Action::ReplaceChar(_)
+                | Action::ToggleCase
+                | Action::IndentLines
+                | Action::DedentLines
+                | Action::PasteAfter
+                | Action::PasteBefore
+                | Action::ChangeToLineEnd
+                | Action::ChangeLine
+                | Action::AppendAfterCursor
+                | Action::AppendAtLineEnd
+                | Action::InsertAtFirstNonBlank
+                | Action::OpenLineBelow
+                | Action::OpenLineAbove
+                | Action::EnterMode(Mode::Insert | Mode::Replace)
+        )
+    }
+
+    // actions that a count prefix repeats, like `3j`
+    fn repeats_with_count(&self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveWordForward
+                | Action::MoveWordBackward
+                | Action::MoveWordEnd
+                | Action::FindChar(_, _)
+                | Action::RepeatFind
+                | Action::RepeatFindReverse
+                | Action::MoveParagraphForward
+                | Action::MoveParagraphBackward
+                | Action::SearchNext
+                | Action::SearchPrev
+                | Action::DeleteCharAtCursorPos
+        )
+    }
+
+    // changes that aren't recorded for undo but still make the redo history stale
+    fn edits_buffer(&self) -> bool {
+        matches!(
+            self,
+            Action::InsertCharAtCursorPos(_)
+                | Action::InsertTab
+                | Action::DeleteCharAtCursorPos
+                | Action::DeleteCharBeforeCursor
+                | Action::NewLine
+                | Action::OverwriteChar(_)
+                | Action::RestoreOverwrittenChar
+        )
+    }
+}
+
+// the actions key bindings can name, in the config file and in the built-in keymaps below
+fn named_action(name: &str) -> Option<Action> {
+    Some(match name {
+        "save" => Action::Save,
+        "undo" => Action::Undo,
+        "redo" => Action::Redo,
+        // these wait for the key that says what to record into, replace with, find and so on
+        "record_macro" => Action::SetWaitingCmd('q'),
+        "play_macro" => Action::SetWaitingCmd('@'),
+        "replace_char" => Action::SetWaitingCmd('r'),
+        "delete" => Action::SetWaitingCmd('d'),
+        "change" => Action::SetWaitingCmd('c'),
+        "find_char" => Action::SetWaitingCmd('f'),
+        "find_char_backward" => Action::SetWaitingCmd('F'),
+        "till_char" => Action::SetWaitingCmd('t'),
+        "till_char_backward" => Action::SetWaitingCmd('T'),
+        "select_register" => Action::SetWaitingCmd('"'),
+        "set_mark" => Action::SetWaitingCmd('m'),
+        "jump_to_mark" => Action::SetWaitingCmd('`'),
+        "jump_to_mark_line" => Action::SetWaitingCmd('\''),
+        "move_up" => Action::MoveUp,
+        "move_down" => Action::MoveDown,
+        "move_left" => Action::MoveLeft,
+        "move_right" => Action::MoveRight,
+        "line_start" => Action::MoveToLineStart,
+        "line_end" => Action::MoveToLineEnd,
+        "word_forward" => Action::MoveWordForward,
+        "word_backward" => Action::MoveWordBackward,
+        "word_end" => Action::MoveWordEnd,
+        "repeat_find" => Action::RepeatFind,
+        "repeat_find_reverse" => Action::RepeatFindReverse,
+        "match_bracket" => Action::MatchBracket,
+        "paragraph_forward" => Action::MoveParagraphForward,
+        "paragraph_backward" => Action::MoveParagraphBackward,
+        "page_up" => Action::PageUp,
+        "page_down" => Action::PageDown,
+        "half_page_up" => Action::HalfPageUp,
+        "half_page_down" => Action::HalfPageDown,
+        "first_line" => Action::GoToFirstLine,
+        "last_line" => Action::GoToLastLine,
+        "search_next" => Action::SearchNext,
+        "search_prev" => Action::SearchPrev,
+        "jump_back" => Action::JumpBack,
+        "jump_forward" => Action::JumpForward,
+        "next_diagnostic" => Action::NextDiagnostic,
+        "prev_diagnostic" => Action::PrevDiagnostic,
+        "center_line" => Action::MoveLineToViewportCenter,
+        "line_to_top" => Action::MoveLineToViewportTop,
+        "line_to_bottom" => Action::MoveLineToViewportBottom,
+        "delete_char" => Action::DeleteCharAtCursorPos,
+        "delete_char_before" => Action::DeleteCharBeforeCursor,
+        "delete_line" => Action::DeleteCurrentLine,
+        "delete_to_line_end" => Action::DeleteToLineEnd,
+        "change_line" => Action::ChangeLine,
+        "change_to_line_end" => Action::ChangeToLineEnd,
+        "yank_line" => Action::YankLine,
+        "paste_after" => Action::PasteAfter,
+        "paste_before" => Action::PasteBefore,
+        "repeat_change" => Action::RepeatChange,
+        "toggle_case" => Action::ToggleCase,
+        "indent" => Action::IndentLines,
+        "dedent" => Action::DedentLines,
+        "new_line" => Action::NewLine,
+        "insert_tab" => Action::InsertTab,
+        "append" => Action::AppendAfterCursor,
+        "append_at_line_end" => Action::AppendAtLineEnd,
+        "insert_at_first_non_blank" => Action::InsertAtFirstNonBlank,
+        "open_line_below" => Action::OpenLineBelow,
+        "open_line_above" => Action::OpenLineAbove,
+        "normal_mode" => Action::EnterMode(Mode::Normal),
+        "insert_mode" => Action::EnterMode(Mode::Insert),
+        "replace_mode" => Action::EnterMode(Mode::Replace),
+        "visual_mode" => Action::EnterMode(Mode::Visual),
+        "visual_line_mode" => Action::EnterMode(Mode::VisualLine),
+        "visual_block_mode" => Action::EnterMode(Mode::VisualBlock),
+        "command_mode" => Action::EnterMode(Mode::Command),
+        "search_mode" => Action::EnterMode(Mode::Search),
+        "open_finder" => Action::OpenFinder,
+        "alternate_buffer" => Action::SwitchToAlternateBuffer,
+        "file_info" => Action::ShowFileInfo,
+        _ => return None,
+    })
+}
+
+// the built-in normal mode keys, which the config file's `[normal]` section goes on top of.
+// Counts, and the keys after one that waits like `d` or `f`, are handled before these
+const NORMAL_KEYS: &[(&str, &str)] = &[
+    ("h", "move_left"),
+    ("j", "move_down"),
+    ("k", "move_up"),
+    ("l", "move_right"),
+    ("left", "move_left"),
+    ("down", "move_down"),
+    ("up", "move_up"),
+    ("right", "move_right"),
+    ("0", "line_start"),
+    ("home", "line_start"),
+    ("$", "line_end"),
+    ("end", "line_end"),
+    ("w", "word_forward"),
+    ("b", "word_backward"),
+    ("e", "word_end"),
+    ("f", "find_char"),
+    ("F", "find_char_backward"),
+    ("t", "till_char"),
+    ("T", "till_char_backward"),
+    (";", "repeat_find"),
+    (",", "repeat_find_reverse"),
+    ("%", "match_bracket"),
+    ("}", "paragraph_forward"),
+    ("{", "paragraph_backward"),
+    ("gg", "first_line"),
+    ("G", "last_line"),
+    ("ctrl-b", "page_up"),
+    ("ctrl-f", "page_down"),
+    ("pageup", "page_up"),
+    ("pagedown", "page_down"),
+    ("ctrl-u", "half_page_up"),
+    ("ctrl-d", "half_page_down"),
+    ("zz", "center_line"),
+    ("zt", "line_to_top"),
+    ("zb", "line_to_bottom"),
+    ("n", "search_next"),
+    ("N", "search_prev"),
+    ("m", "set_mark"),
+    ("`", "jump_to_mark"),
+    ("'", "jump_to_mark_line"),
+    ("ctrl-o", "jump_back"),
+    ("ctrl-i", "jump_forward"),
+    // terminals send Ctrl-i as Tab
+    ("tab", "jump_forward"),
+    ("]d", "next_diagnostic"),
+    ("[d", "prev_diagnostic"),
+    ("i", "insert_mode"),
+    ("a", "append"),
+    ("A", "append_at_line_end"),
+    ("I", "insert_at_first_non_blank"),
+    ("o", "open_line_below"),
+    ("O", "open_line_above"),
+    ("R", "replace_mode"),
+    ("v", "visual_mode"),
+    ("V", "visual_line_mode"),
+    ("ctrl-v", "visual_block_mode"),
+    (":", "command_mode"),
+    ("/", "search_mode"),
+    ("x", "delete_char"),
+    ("d", "delete"),
+    ("D", "delete_to_line_end"),
+    ("c", "change"),
+    ("C", "change_to_line_end"),
+    ("r", "replace_char"),
+    ("~", "toggle_case"),
+    (">>", "indent"),
+    ("<<", "dedent"),
+    ("yy", "yank_line"),
+    ("\"", "select_register"),
+    ("p", "paste_after"),
+    ("P", "paste_before"),
+    (".", "repeat_change"),
+    ("u", "undo"),
+    ("ctrl-r", "redo"),
+    ("q", "record_macro"),
+    ("@", "play_macro"),
+    ("ctrl-s", "save"),
+    ("ctrl-p", "open_finder"),
+    ("ctrl-g", "file_info"),
+    ("ctrl-^", "alternate_buffer"),
+    ("ctrl-6", "alternate_buffer"),
+];
+
+// the built-in insert mode keys. Any other character is typed into the buffer
+const INSERT_KEYS: &[(&str, &str)] = &[
+    ("esc", "normal_mode"),
+    ("enter", "new_line"),
+    ("backspace", "delete_char_before"),
+    ("tab", "insert_tab"),
+];
+
+// the motions that can follow an operator like `d`, taking in what they move over
+fn motion(code: event::KeyCode) -> Option<Action> {
+    Some(match code {
+        event::KeyCode::Char('w') => Action::MoveWordForward,
+        event::KeyCode::Char('b') => Action::MoveWordBackward,
+        event::KeyCode::Char('e') => Action::MoveWordEnd,
+        event::KeyCode::Char('h') | event::KeyCode::Left => Action::MoveLeft,
+        event::KeyCode::Char('l') | event::KeyCode::Right => Action::MoveRight,
+        event::KeyCode::Char('0') | event::KeyCode::Home => Action::MoveToLineStart,
+        event::KeyCode::Char('$') | event::KeyCode::End => Action::MoveToLineEnd,
+        _ => return None,
+    })
+}
+
+// the changes one `u` takes back, like everything done by a single command or typed in one
+// insert session. Each is an `ApplyEdit`, applied last first
+struct UndoGroup(Vec<Action>);
+
+// how many seconds an info message stays in the command line
+const MESSAGE_TIMEOUT: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageKind {
+    Info,
+    Error,
 }
 
 #[derive(Debug, Clone, Copy)]
 enum Mode {
     Normal,
     Insert,
+    Replace,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    Command,
+    Search,
+    Terminal,
+    Finder,
+}
+
+// text typed with `I` or `A` on the first line of a visual block, to be copied to the rest of
+// its lines when insert mode is left
+struct BlockInsert {
+    top: usize,
+    bottom: usize,
+    // the block's column, where the text goes on the other lines
+    col: usize,
+    // where the typing started on the first line
+    from: usize,
+    // `A` pads lines that are too short to reach the column, `I` leaves them alone
+    append: bool,
+}
+
+// how deep macros can play other macros, or themselves
+const MAX_MACRO_DEPTH: usize = 100;
+
+// how many positions the jumplist remembers
+const JUMPLIST_SIZE: usize = 100;
+
+// buffers drawing reuses from frame to frame, so typical frames don't allocate
+#[derive(Default)]
+struct DrawBuffers {
+    // the buffer line and first display cell drawn on each screen row
+    screen_rows: Vec<(usize, usize)>,
+    rendered: String,
+    line: String,
+    row: String,
+}
+
+// a buffer that isn't on screen, along with the view state to restore when switching back
+struct BufferView {
+    buffer: Buffer,
+    vtop: usize,
+    vleft: u16,
+    cx: u16,
+    cy: u16,
+    undo_actions: Vec<UndoGroup>,
+    redo_actions: Vec<UndoGroup>,
+    marks: HashMap<char, (usize, usize)>,
+    jumplist: Vec<(usize, usize)>,
+    jump_index: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl BufferView {
+    fn new(buffer: Buffer) -> Self {
+        Self {
+            buffer,
+            vtop: 0,
+            vleft: 0,
+            cx: 0,
+            cy: 0,
+            undo_actions: vec![],
+            redo_actions: vec![],
+            marks: HashMap::new(),
+            jumplist: vec![],
+            jump_index: 0,
+            diagnostics: vec![],
+        }
+    }
 }
 
 pub struct Editor {
     buffer: Buffer,
-    stdout: std::io::Stdout,
+    stdout: Box<dyn Write>,
+    // whether the terminal was put in raw mode and has to be restored on the way out
+    owns_terminal: bool,
     size: (u16, u16),
     vtop: usize,
     vleft: u16,
@@ -52,46 +547,277 @@ pub struct Editor {
     cy: u16,
     mode: Mode,
     waiting_command: Option<char>,
-    undo_actions: Vec<Action>,
+    undo_actions: Vec<UndoGroup>,
+    redo_actions: Vec<UndoGroup>,
+    // the group the current command's changes go into, open until it finishes or, for those
+    // that start inserting, until insert mode is left
+    undo_group: Option<UndoGroup>,
+    diagnostics: Vec<Diagnostic>,
+    command: String,
+    message: Option<(String, MessageKind)>,
+    alternate_rules: Vec<Rule>,
+    // the buffer list, without the one on screen, which sits at `buffer_index` among them
+    buffers: Vec<BufferView>,
+    buffer_index: usize,
+    // where in the list the buffer last switched away from is, for `Ctrl-^`
+    alternate: Option<usize>,
+    global_options: Options,
+    // the options in effect for the current buffer, layered again whenever either side changes
+    // rather than every time one is read
+    options: Options,
+    cwd: PathBuf,
+    finder: Option<Finder>,
+    // where the visual selection started, as (line, column)
+    anchor: (usize, usize),
+    block_insert: Option<BlockInsert>,
+    // the unnamed register that yanks and deletes fill
+    register: Register,
+    // the register picked with `"` for the next yank or paste
+    selected_register: Option<char>,
+    search_query: Option<String>,
+    quit: bool,
+    // the last change and the count it was made with, for `.` to repeat
+    last_change: Vec<Action>,
+    last_change_count: Option<usize>,
+    // the last `f`, `F`, `t` or `T`, for `;` and `,` to repeat
+    last_find: Option<(char, FindKind)>,
+    // the characters overwritten in replace mode, or `None` where typing went past the end of
+    // the line, so backspace can put them back
+    overwritten: Vec<Option<char>>,
+    // the count typed before a command, like the 3 in `3j`
+    pending_count: Option<usize>,
+    // the rows drawn last frame, so only rows that changed get printed again. Emptied to
+    // force a full redraw
+    prev_viewport: Vec<String>,
+    draw_buffers: DrawBuffers,
+    // when the current message was shown, for info messages to expire
+    message_time: Instant,
+    // the operator and whether it's `a` rather than `i`, while waiting for a text object
+    waiting_object: Option<(char, bool)>,
+    // the bindings for normal and insert mode: the built-in keys, with the config file's on top
+    normal_keys: Keymap<Action>,
+    insert_keys: Keymap<Action>,
+    // keys typed so far towards a longer binding
+    pending_keys: Vec<Key>,
+    // set while replaying held keys that turned out not to be a binding
+    skip_keymap: bool,
+    // the marks set with `m`, as (line, column)
+    marks: HashMap<char, (usize, usize)>,
+    // where big motions jumped from, as (line, column), for `Ctrl-o` and `Ctrl-i` to walk.
+    // `jump_index` is the entry being visited, or the length when not walking the list
+    jumplist: Vec<(usize, usize)>,
+    jump_index: usize,
+    // the register being recorded into with `q`, and the events typed so far
+    recording: Option<(char, Vec<event::Event>)>,
+    macros: HashMap<char, Vec<event::Event>>,
+    last_macro: Option<char>,
+    macro_depth: usize,
 }
 
 impl Editor {
-    pub fn new(buffer: Buffer) -> anyhow::Result<Self> {
+    // takes over the terminal, with the first buffer on screen and the rest waiting in the
+    // buffer list
+    pub fn new(buffers: Vec<Buffer>) -> anyhow::Result<Self> {
         let mut stdout = stdout();
         terminal::enable_raw_mode()?;
         stdout
             .execute(terminal::EnterAlternateScreen)?
+            .execute(event::EnableBracketedPaste)?
+            .execute(event::EnableMouseCapture)?
             .execute(terminal::Clear(terminal::ClearType::All))?;
 
+        let mut editor = Self::with_output(Box::new(stdout), terminal::size()?)?;
+        editor.owns_terminal = true;
+        let config = match keymap::path() {
+            Some(path) => keymap::load(&path, named_action),
+            None => Ok(keymap::Config::default()),
+        };
+        // options from the config have to be in place before the buffers load, for `undofile`
+        let config = config.map(|config| editor.use_config(config));
+        editor.open_buffers(buffers);
+        // a broken config is reported last, so other startup messages don't hide it
+        if let Err(e) = config {
+            editor.show_error(e);
+        }
+
+        Ok(editor)
+    }
+
+    fn use_config(&mut self, mut config: keymap::Config<Action>) {
+        // rules in the config replace the built-in ones rather than adding to them
+        if !config.alternate.is_empty() {
+            self.alternate_rules = std::mem::take(&mut config.alternate)
+                .iter()
+                .map(|(from, to)| Rule::new(from, to))
+                .collect();
+        }
+        // valid already, since loading checks them
+        for arg in &config.options {
+            _ = self.global_options.set(arg);
+        }
+        self.update_options();
+        self.normal_keys.layer(config.normal);
+        self.insert_keys.layer(config.insert);
+    }
+
+    // an editor drawing to `out` as if the terminal were `size`, leaving the real terminal
+    // alone. It holds an empty buffer until `open_buffers`
+    fn with_output(out: Box<dyn Write>, size: (u16, u16)) -> anyhow::Result<Self> {
         Ok(Self {
-            buffer,
-            stdout,
+            buffer: Buffer::from_file(None)?,
+            stdout: out,
+            owns_terminal: false,
             vtop: 0,
             vleft: 0,
             cx: 0,
             cy: 0,
             mode: Mode::Normal,
             waiting_command: None,
-            size: terminal::size()?,
+            size,
             undo_actions: vec![],
+            redo_actions: vec![],
+            undo_group: None,
+            diagnostics: vec![],
+            command: String::new(),
+            message: None,
+            alternate_rules: alternate::default_rules(),
+            alternate: None,
+            buffers: vec![],
+            buffer_index: 0,
+            global_options: Options::default(),
+            options: Options::default(),
+            cwd: std::env::current_dir()?,
+            finder: None,
+            anchor: (0, 0),
+            block_insert: None,
+            register: Register::default(),
+            selected_register: None,
+            search_query: None,
+            quit: false,
+            last_change: vec![],
+            last_change_count: None,
+            last_find: None,
+            overwritten: vec![],
+            pending_count: None,
+            prev_viewport: vec![],
+            draw_buffers: DrawBuffers::default(),
+            message_time: Instant::now(),
+            waiting_object: None,
+            normal_keys: Keymap::from_names(NORMAL_KEYS, named_action)?,
+            insert_keys: Keymap::from_names(INSERT_KEYS, named_action)?,
+            pending_keys: vec![],
+            skip_keymap: false,
+            marks: HashMap::new(),
+            jumplist: vec![],
+            jump_index: 0,
+            recording: None,
+            macros: HashMap::new(),
+            last_macro: None,
+            macro_depth: 0,
         })
     }
 
+    // puts the first buffer on screen with the rest waiting in the list. Every buffer gets its
+    // indent style and undo history up front
+    fn open_buffers(&mut self, buffers: Vec<Buffer>) {
+        let mut buffers = buffers.into_iter();
+        if let Some(first) = buffers.next() {
+            self.buffer = first;
+            self.update_options();
+        }
+        self.buffers = buffers.map(BufferView::new).collect();
+        self.buffer_index = 0;
+
+        // going through them last first ends up back on the first
+        for n in (0..=self.buffers.len()).rev() {
+            self.switch_to_buffer(n);
+            self.use_buffer_indent();
+            self.load_undofile();
+        }
+        self.alternate = None;
+    }
+
+    // puts the cursor on a 1-based line, as asked for with `+42` on the command line
+    pub fn go_to_start_line(&mut self, line: usize) {
+        let last = self.buffer.len().saturating_sub(1);
+        self.go_to_line(line.saturating_sub(1).min(last));
+    }
+
+    // the options in effect for the current buffer
+    fn options(&self) -> &Options {
+        &self.options
+    }
+
+    fn update_options(&mut self) {
+        self.options = self.global_options.layered(&self.buffer.options);
+    }
+
     fn vwidth(&self) -> u16 {
-        self.size.0
+        self.size
+            .0
+            .saturating_sub(self.gutter_width() + self.scrollbar_width())
+    }
+
+    fn scrollbar_width(&self) -> u16 {
+        if self.options().scrollbar {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn gutter_width(&self) -> u16 {
+        self.sign_width() + self.number_width()
+    }
+
+    // the sign column is only shown while there are diagnostics to display
+    fn sign_width(&self) -> u16 {
+        if self.diagnostics.is_empty() {
+            0
+        } else {
+            2
+        }
+    }
+
+    // wide enough for the largest line number plus a space before the text
+    fn number_width(&self) -> u16 {
+        let options = self.options();
+        if options.number || options.relativenumber {
+            self.buffer.len().max(1).ilog10() as u16 + 2
+        } else {
+            0
+        }
+    }
+
+    pub fn set_diagnostics(&mut self, mut diagnostics: Vec<Diagnostic>) {
+        diagnostics.sort_by_key(|d| (d.line, d.col));
+        self.diagnostics = diagnostics;
     }
 
     fn vheight(&self) -> u16 {
-        self.size.1 - 2
+        self.size.1.saturating_sub(2)
     }
 
     fn line_length(&self) -> u16 {
         if let Some(line) = self.viewport_line(self.cy) {
-            return line.len() as u16;
+            return line.chars().count() as u16;
         }
         0
     }
 
+    // the screen column the cursor is drawn at, which differs from `cx` once tabs are involved
+    fn cursor_cell(&self) -> u16 {
+        let line = self.viewport_line(self.cy).unwrap_or_default();
+        text::display_col(&line, self.cx as usize, self.options().tabstop) as u16
+    }
+
+    // places the cursor on the character drawn at `cell`, so vertical motions keep the cursor
+    // in the same screen column across lines with tabs
+    fn move_to_cell(&mut self, cell: u16) {
+        let line = self.viewport_line(self.cy).unwrap_or_default();
+        self.cx = text::logical_col(&line, cell as usize, self.options().tabstop) as u16;
+    }
+
     fn buffer_line(&self) -> usize {
         self.vtop + self.cy as usize
     }
@@ -105,9 +831,19 @@ impl Editor {
     fn set_cursor_style(&mut self) -> anyhow::Result<()> {
         self.stdout.queue(match self.waiting_command {
             Some(_) => cursor::SetCursorStyle::SteadyUnderScore,
+            _ if self.waiting_object.is_some() => cursor::SetCursorStyle::SteadyUnderScore,
+            // partway through a binding like `gg`
+            _ if matches!(self.mode, Mode::Normal) && !self.pending_keys.is_empty() => {
+                cursor::SetCursorStyle::SteadyUnderScore
+            }
             _ => match self.mode {
-                Mode::Normal => cursor::SetCursorStyle::DefaultUserShape,
-                Mode::Insert => cursor::SetCursorStyle::SteadyBar,
+                Mode::Normal | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                    cursor::SetCursorStyle::DefaultUserShape
+                }
+                Mode::Replace => cursor::SetCursorStyle::SteadyUnderScore,
+                Mode::Insert | Mode::Command | Mode::Search | Mode::Terminal | Mode::Finder => {
+                    cursor::SetCursorStyle::SteadyBar
+                }
             },
         })?;
 
@@ -116,36 +852,311 @@ impl Editor {
 
     pub fn draw(&mut self) -> anyhow::Result<()> {
         self.set_cursor_style()?;
-        self.draw_viewport()?;
+        match self.mode {
+            Mode::Finder => self.draw_finder()?,
+            _ => self.draw_viewport()?,
+        }
         self.draw_status_line()?;
-        self.stdout.queue(cursor::MoveTo(self.cx, self.cy))?;
+        self.draw_command_line()?;
+        let (x, y) = self.terminal_cursor_pos();
+        self.stdout.queue(cursor::MoveTo(x, y))?;
         self.stdout.flush()?;
 
         Ok(())
     }
 
+    // where the terminal cursor goes: after the typed text on the command line, or on the
+    // buffer's cursor. Wide characters take two cells here as well
+    fn terminal_cursor_pos(&self) -> (u16, u16) {
+        let tabstop = self.options().tabstop;
+        let last_row = self.size.1.saturating_sub(1);
+        match (&self.mode, &self.finder) {
+            (Mode::Command | Mode::Search, _) => (
+                text::display_width(&self.command, tabstop) as u16 + 1,
+                last_row,
+            ),
+            (Mode::Finder, Some(finder)) => (
+                text::display_width(&finder.query, tabstop) as u16 + 2,
+                last_row,
+            ),
+            _ => self.cursor_screen_pos(),
+        }
+    }
+
     pub fn draw_viewport(&mut self) -> anyhow::Result<()> {
+        // everything that stays the same across rows is worked out once per frame, and the
+        // buffers rows are built in are kept between frames so typical frames don't allocate
+        let Options {
+            tabstop,
+            list,
+            wrap,
+            ..
+        } = *self.options();
         let vwidth = self.vwidth() as usize;
-        for i in 0..self.vheight() {
-            let line = match self.viewport_line(i) {
-                None => String::new(),
-                Some(s) => s,
-            };
-            self.stdout
-                .queue(cursor::MoveTo(0, i))?
-                .queue(style::Print(format!("{line:<width$}", width = vwidth)))?;
+        let vheight = self.vheight();
+        let gutter_width = self.gutter_width() as usize;
+        let number_width = self.number_width() as usize;
+        let thumb = self.scrollbar_thumb();
+
+        let mut buffers = std::mem::take(&mut self.draw_buffers);
+        let DrawBuffers {
+            screen_rows,
+            rendered,
+            line,
+            row,
+        } = &mut buffers;
+        self.fill_screen_rows(screen_rows);
+        self.prev_viewport.resize(vheight as usize, String::new());
+        for (i, &(buffer_line, vleft)) in screen_rows.iter().enumerate() {
+            let i = i as u16;
+            let raw = self.buffer.line(buffer_line).unwrap_or_default();
+            rendered.clear();
+            text::render(raw, tabstop, list, rendered);
+            line.clear();
+            text::slice_cells(rendered, vleft, vwidth, line);
+            // pad by cells rather than characters, since wide characters take two
+            let padding = vwidth.saturating_sub(text::display_width(line, tabstop));
+
+            // the row is built up with its styling first, and only printed if it changed
+            row.clear();
+            if wrap && vleft > 0 {
+                // the rows a wrapped line carries on over leave the gutter blank
+                write!(row, "{:gutter_width$}", "")?;
+            } else {
+                self.draw_sign(row, buffer_line)?;
+                self.draw_number(row, buffer_line, number_width)?;
+            }
+
+            match self.selected_cols(buffer_line) {
+                Some((start, end)) => {
+                    // the selection is in logical columns, which have to go through display
+                    // cells to find where they landed in the visible part of the line
+                    let split = |col| {
+                        let cell = text::display_col(raw, col, tabstop)
+                            .saturating_sub(vleft)
+                            .min(vwidth);
+                        text::byte_offset(line, text::logical_col(line, cell, tabstop))
+                    };
+                    let (start, end) = (split(start), split(end));
+
+                    write!(
+                        row,
+                        "{}{}{}{:padding$}",
+                        &line[..start],
+                        line[start..end].on(Color::Rgb {
+                            r: 67,
+                            g: 70,
+                            b: 89,
+                        }),
+                        &line[end..],
+                        ""
+                    )?;
+                }
+                None => {
+                    write!(row, "{line}{:padding$}", "")?;
+                }
+            }
+            self.draw_scrollbar(row, i, thumb)?;
+
+            let prev = &mut self.prev_viewport[i as usize];
+            if prev != row {
+                self.stdout
+                    .queue(cursor::MoveTo(0, i))?
+                    .queue(style::Print(&row))?;
+                prev.clone_from(row);
+            }
+        }
+        self.draw_buffers = buffers;
+        Ok(())
+    }
+
+    // lists the files matching the finder's query, best first, keeping the selection in view
+    fn draw_finder(&mut self) -> anyhow::Result<()> {
+        let Some(finder) = &self.finder else {
+            return Ok(());
+        };
+
+        // the finder draws over the viewport, so it has to be drawn in full afterwards
+        self.prev_viewport.clear();
+
+        let width = self.size.0 as usize;
+        let vheight = self.vheight() as usize;
+        let top = finder.selected.saturating_sub(vheight.saturating_sub(1));
+
+        for row in 0..vheight {
+            let i = top + row;
+            let file = finder.matches.get(i).map_or("", |s| s.as_str());
+            let line = format!("{file:<width$.width$}");
+
+            self.stdout.queue(cursor::MoveTo(0, row as u16))?;
+            if i == finder.selected && !file.is_empty() {
+                self.stdout.queue(style::PrintStyledContent(
+                    line.with(Color::Rgb { r: 0, g: 0, b: 0 }).on(Color::Rgb {
+                        r: 184,
+                        g: 144,
+                        b: 243,
+                    }),
+                ))?;
+            } else {
+                self.stdout.queue(style::Print(line))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the thumb covers the visible share of the buffer, offset by how far down `vtop` is. As
+    // (top, height) in rows, or `None` with the scrollbar off
+    fn scrollbar_thumb(&self) -> Option<(usize, usize)> {
+        if self.scrollbar_width() == 0 {
+            return None;
+        }
+
+        // a terminal too short for any text still gets a one-row thumb
+        let vheight = (self.vheight() as usize).max(1);
+        let len = self.buffer.len().max(vheight);
+        let thumb_height = (vheight * vheight / len).max(1);
+        let thumb_top = (self.vtop * vheight / len).min(vheight - thumb_height);
+        Some((thumb_top, thumb_height))
+    }
+
+    fn draw_scrollbar(
+        &self,
+        out: &mut String,
+        row: u16,
+        thumb: Option<(usize, usize)>,
+    ) -> anyhow::Result<()> {
+        let Some((thumb_top, thumb_height)) = thumb else {
+            return Ok(());
+        };
+
+        let row = row as usize;
+        let glyph = if row >= thumb_top && row < thumb_top + thumb_height {
+            "█"
+        } else {
+            "│"
+        };
+
+        write!(
+            out,
+            "{}",
+            glyph.with(Color::Rgb {
+                r: 108,
+                g: 112,
+                b: 134,
+            })
+        )?;
+
+        Ok(())
+    }
+
+    fn draw_sign(&self, out: &mut String, line: usize) -> anyhow::Result<()> {
+        if self.sign_width() == 0 {
+            return Ok(());
+        }
+
+        match diagnostic::for_line(&self.diagnostics, line) {
+            Some(d) => write!(
+                out,
+                "{}",
+                style::style(format_args!("{} ", d.severity.icon())).with(d.severity.color())
+            )?,
+            None => out.push_str("  "),
+        };
+
+        Ok(())
+    }
+
+    // rows past the end of the buffer get a `~` instead of a number, like vim
+    fn draw_number(&self, out: &mut String, line: usize, width: usize) -> anyhow::Result<()> {
+        if width == 0 {
+            return Ok(());
+        }
+        let options = self.options();
+
+        // with relativenumber, other lines show their distance from the cursor line, and the
+        // cursor line itself shows its number when number is also set
+        let current = self.buffer_line();
+        style::SetForegroundColor(Color::Rgb {
+            r: 108,
+            g: 112,
+            b: 134,
+        })
+        .write_ansi(out)?;
+        if line >= self.buffer.len().max(1) {
+            write!(out, "{:<width$}", "~")?;
+        } else if !options.relativenumber {
+            write!(out, "{:>width$} ", line + 1, width = width - 1)?;
+        } else if line != current {
+            write!(
+                out,
+                "{:>width$} ",
+                line.abs_diff(current),
+                width = width - 1
+            )?;
+        } else if options.number {
+            write!(out, "{:<width$} ", line + 1, width = width - 1)?;
+        } else {
+            write!(out, "{:>width$} ", 0, width = width - 1)?;
         }
+        style::ResetColor.write_ansi(out)?;
+
         Ok(())
     }
 
     pub fn draw_status_line(&mut self) -> anyhow::Result<()> {
         let mode = format!(" {:?} ", self.mode).to_uppercase();
-        let file = format!(" {}", self.buffer.file.as_deref().unwrap_or("No Name"));
-        let pos = format!(" {}:{} ", self.cx, self.cy);
+        let mode = match self.recording {
+            Some((c, _)) => format!("{mode}recording @{c} "),
+            None => mode,
+        };
+        let file = match self.buffer.modified {
+            true => format!("{} [+]", self.buffer.name()),
+            false => self.buffer.name().to_string(),
+        };
+        // where the buffer is in the list, when there's more than one
+        let file = match self.buffers.len() {
+            0 => file,
+            others => format!("{file} [{}/{}]", self.buffer_index + 1, others + 1),
+        };
+        let file = match diagnostic::for_line(&self.diagnostics, self.buffer_line()) {
+            Some(d) => format!(" {} {} {}", file, d.severity.icon(), d.message),
+            None => format!(" {}", file),
+        };
+        // the position in the file, 1-based like vim's ruler
+        let (line, col) = (self.buffer_line() + 1, self.cx + 1);
+        let pos = match diagnostic::summary(&self.diagnostics).as_str() {
+            "" => format!(" {line}:{col} "),
+            summary => format!(" {summary} {line}:{col} "),
+        };
+
+        let width = self.size.0 as usize;
+        self.stdout
+            .queue(cursor::MoveTo(0, self.size.1.saturating_sub(2)))?;
+
+        if width < mode.len() + 2 {
+            // not even room for the separators, so all that's left is the mode
+            self.stdout.queue(style::PrintStyledContent(
+                format!("{mode:<width$.width$}")
+                    .with(Color::Rgb { r: 0, g: 0, b: 0 })
+                    .bold()
+                    .on(Color::Rgb {
+                        r: 184,
+                        g: 144,
+                        b: 243,
+                    }),
+            ))?;
+            return Ok(());
+        }
 
-        let file_width = self.size.0 - mode.len() as u16 - pos.len() as u16 - 2;
+        // when columns are scarce, drop the file name first and then the position
+        let (file, pos) = if width >= mode.len() + pos.len() + 2 {
+            (file, pos)
+        } else {
+            (String::new(), String::new())
+        };
+        let file_width = width.saturating_sub(mode.len() + pos.len() + 2);
 
-        self.stdout.queue(cursor::MoveTo(0, self.size.1 - 2))?;
         self.stdout.queue(style::PrintStyledContent(
             mode.with(Color::Rgb { r: 0, g: 0, b: 0 })
                 .bold()
@@ -171,7 +1182,7 @@ impl Editor {
         ))?;
 
         self.stdout.queue(style::PrintStyledContent(
-            format!("{:<width$}", file, width = file_width as usize)
+            format!("{:<width$.width$}", file, width = file_width)
                 .with(Color::Rgb {
                     r: 255,
                     g: 255,
@@ -212,39 +1223,244 @@ impl Editor {
         Ok(())
     }
 
-    pub fn check_bounds(&mut self) {
-        let line_length = self.line_length();
+    fn mode_indicator(&self) -> Option<&'static str> {
+        match self.mode {
+            Mode::Insert => Some("-- INSERT --"),
+            Mode::Replace => Some("-- REPLACE --"),
+            Mode::Visual => Some("-- VISUAL --"),
+            Mode::VisualLine => Some("-- VISUAL LINE --"),
+            Mode::VisualBlock => Some("-- VISUAL BLOCK --"),
+            Mode::Terminal => Some("-- TERMINAL --"),
+            Mode::Finder => None,
+            Mode::Normal | Mode::Command | Mode::Search => None,
+        }
+    }
 
-        if self.cx >= line_length {
-            if line_length > 0 {
-                self.cx = self.line_length() - 1;
-            } else {
-                self.cx = 0;
-            }
+    // the last row shows the command being typed, or the result of the last one
+    pub fn draw_command_line(&mut self) -> anyhow::Result<()> {
+        let (line, kind) = match (&self.mode, &self.message) {
+            (Mode::Command, _) => (format!(":{}", self.command), MessageKind::Info),
+            (Mode::Search, _) => (format!("/{}", self.command), MessageKind::Info),
+            (Mode::Finder, _) => match &self.finder {
+                Some(finder) => (format!("> {}", finder.query), MessageKind::Info),
+                None => (String::new(), MessageKind::Info),
+            },
+            (_, Some((message, kind))) => (message.clone(), *kind),
+            (_, None) => match self.mode_indicator() {
+                Some(indicator) if self.options().showmode => {
+                    (indicator.to_string(), MessageKind::Info)
+                }
+                _ => (String::new(), MessageKind::Info),
+            },
+        };
+        let line = format!("{line:<width$.width$}", width = self.size.0 as usize);
+
+        self.stdout
+            .queue(cursor::MoveTo(0, self.size.1.saturating_sub(1)))?;
+        match kind {
+            MessageKind::Info => self.stdout.queue(style::Print(line))?,
+            MessageKind::Error => self
+                .stdout
+                .queue(style::PrintStyledContent(line.with(Color::Red)))?,
+        };
+
+        Ok(())
+    }
+
+    pub fn check_bounds(&mut self) {
+        // while inserting, the cursor can sit just past the last character to append to the line
+        let last_col = match self.mode {
+            Mode::Insert | Mode::Replace => self.line_length(),
+            _ => self.line_length().saturating_sub(1),
+        };
+        if self.cx > last_col {
+            self.cx = last_col;
         }
 
-        if self.cx >= self.vwidth() {
-            self.cx = self.vwidth() - 1;
+        // scroll sideways to keep the cursor on screen; `vleft` is the first display cell shown
+        let cell = self.cursor_cell();
+        let vwidth = self.vwidth().max(1);
+        let wrap = self.options().wrap;
+        if wrap {
+            self.vleft = 0;
+        } else if cell < self.vleft {
+            self.vleft = cell;
+        } else if cell >= self.vleft + vwidth {
+            self.vleft = cell + 1 - vwidth;
         }
 
         // check if cy is after the end of the buffer
-        // the end of the buffer is less than vtop + cy
-        let line_on_buffer = self.cy as usize + self.vtop;
-        if line_on_buffer > self.buffer.len() - 1 {
-            self.cy = (self.buffer.len() - self.vtop - 1) as u16;
+        // the end of the buffer is less than vtop + cy. An empty buffer still has line 0
+        let last = self.buffer.len().saturating_sub(1);
+        if self.vtop > last {
+            self.vtop = last;
+        }
+        if self.buffer_line() > last {
+            self.cy = (last - self.vtop) as u16;
+        }
+
+        // keep `scrolloff` lines in view around the cursor, except where the buffer ends
+        let vheight = (self.vheight() as usize).max(1);
+        let scrolloff = self.scrolloff();
+        let line = self.buffer_line();
+        if line < self.vtop + scrolloff {
+            self.vtop = line.saturating_sub(scrolloff);
+        } else {
+            let max_vtop = self.buffer.len().saturating_sub(vheight);
+            let vtop = (line + scrolloff + 1).saturating_sub(vheight).min(max_vtop);
+            self.vtop = self.vtop.max(vtop);
+        }
+        self.cy = (line - self.vtop) as u16;
+
+        // wrapped lines above the cursor can take several rows each, so scroll until the
+        // cursor's row is on screen
+        while wrap && self.cy > 0 && self.cursor_screen_pos().1 >= vheight as u16 {
+            self.vtop += 1;
+            self.cy -= 1;
+        }
+    }
+
+    // fills `rows` with the buffer line and first display cell drawn on each screen row.
+    // Without wrap, each line takes one row starting at `vleft`; with it, long lines carry on
+    // over as many rows as they need
+    fn fill_screen_rows(&self, rows: &mut Vec<(usize, usize)>) {
+        let vheight = self.vheight() as usize;
+        let Options { wrap, tabstop, .. } = *self.options();
+        rows.clear();
+        if !wrap {
+            rows.extend((0..vheight).map(|i| (self.vtop + i, self.vleft as usize)));
+            return;
+        }
+
+        let vwidth = (self.vwidth() as usize).max(1);
+        let mut line = self.vtop;
+        while rows.len() < vheight {
+            let count = self.wrapped_rows(line, vwidth, tabstop);
+            rows.extend(
+                (0..count)
+                    .map(|k| (line, k * vwidth))
+                    .take(vheight - rows.len()),
+            );
+            line += 1;
+        }
+    }
+
+    // how many screen rows a line takes when wrapped at `vwidth` cells
+    fn wrapped_rows(&self, line: usize, vwidth: usize, tabstop: usize) -> usize {
+        let width = self
+            .buffer
+            .line(line)
+            .map_or(0, |l| text::display_width(l, tabstop));
+        width.div_ceil(vwidth).max(1)
+    }
+
+    // where the cursor is drawn, as (column, row) on screen
+    fn cursor_screen_pos(&self) -> (u16, u16) {
+        let cell = self.cursor_cell() as usize;
+        let gutter = self.gutter_width();
+        let Options { wrap, tabstop, .. } = *self.options();
+        if !wrap {
+            return (
+                cell.saturating_sub(self.vleft as usize) as u16 + gutter,
+                self.cy,
+            );
         }
+
+        let vwidth = (self.vwidth() as usize).max(1);
+        let line = self.buffer_line();
+        let above: usize = (self.vtop..line)
+            .map(|l| self.wrapped_rows(l, vwidth, tabstop))
+            .sum();
+        // just past the end of a line that fills its last row, the cursor stays on that row
+        let rows = self.wrapped_rows(line, vwidth, tabstop);
+        let (row, col) = match cell / vwidth < rows {
+            true => (cell / vwidth, cell % vwidth),
+            false => (rows - 1, vwidth - 1),
+        };
+        (col as u16 + gutter, (above + row) as u16)
+    }
+
+    // `scrolloff`, shrunk so the margins above and below the cursor fit on screen together
+    fn scrolloff(&self) -> usize {
+        let vheight = self.vheight() as usize;
+        self.options().scrolloff.min(vheight.saturating_sub(1) / 2)
     }
 
     pub fn run(&mut self) -> anyhow::Result<()> {
+        let mut redraw = true;
         loop {
-            self.check_bounds();
-            self.draw()?;
-            if let Some(action) = self.handle_event(read()?)? {
-                if matches!(action, Action::Quit) {
-                    break;
-                }
+            if redraw {
+                self.check_bounds();
+                self.draw()?;
+            }
+
+            // wake up now and then rather than blocking on keys, so a running shell can keep
+            // printing and messages can expire
+            let timeout = match self.buffer.terminal {
+                Some(_) => Duration::from_millis(50),
+                None => Duration::from_millis(100),
+            };
+            if !event::poll(timeout)? {
+                redraw = self.update_terminal() | self.expire_message();
+                continue;
+            }
+            redraw = true;
+
+            let ev = read()?;
+            if let Some((_, events)) = &mut self.recording {
+                events.push(ev.clone());
+            }
+            self.process_event(ev)?;
+
+            if self.quit {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // handles an event read from the terminal or replayed from a macro
+    fn process_event(&mut self, ev: event::Event) -> anyhow::Result<()> {
+        // errors stay up until the next keystroke so they aren't missed
+        if matches!(ev, event::Event::Key(_))
+            && matches!(self.message, Some((_, MessageKind::Error)))
+        {
+            self.message = None;
+        }
+
+        if let Some(action) = self.handle_event(ev)? {
+            if action.clears_message() {
+                self.message = None;
+            }
+
+            self.record_change(&action);
+            if self.undo_group.is_none() {
+                self.undo_group = Some(UndoGroup(vec![]));
+            }
+
+            // other actions that use the count take it while executing
+            let repeat = match action.repeats_with_count() {
+                true => self.pending_count.take().unwrap_or(1),
+                false => 1,
+            };
+            for _ in 0..repeat {
                 self.execute(&action);
             }
+            if !matches!(self.mode, Mode::Insert | Mode::Replace) {
+                self.close_undo_group();
+            }
+
+            // the count and register carry over a waiting command, so `"+3dd` sees them
+            if !matches!(
+                action,
+                Action::SetWaitingCmd(_)
+                    | Action::AwaitTextObject(_, _)
+                    | Action::SelectRegister(_)
+            ) {
+                self.pending_count = None;
+                self.selected_register = None;
+            }
         }
 
         Ok(())
@@ -253,77 +1469,275 @@ impl Editor {
     fn handle_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
         if matches!(ev, event::Event::Resize(_, _)) {
             self.size = terminal::size()?;
+            self.prev_viewport.clear();
+        }
+        if let event::Event::Mouse(mouse) = ev {
+            return Ok(self.handle_mouse_event(mouse));
         }
-
         match self.mode {
             Mode::Normal => self.handle_normal_event(ev),
             Mode::Insert => self.handle_insert_event(ev),
+            Mode::Replace => self.handle_replace_event(ev),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => self.handle_visual_event(ev),
+            Mode::Command | Mode::Search => self.handle_command_event(ev),
+            Mode::Terminal => self.handle_terminal_event(ev),
+            Mode::Finder => self.handle_finder_event(ev),
         }
     }
 
-    fn handle_normal_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
-        log!("Event {:?}", ev);
+    // looks the key up in the current mode's bindings. Keys that start a longer binding are held
+    // until it's complete, or replayed one at a time once it can't be. Returns `None` when the
+    // key isn't part of a binding
+    fn handle_mapped_key(&mut self, ev: &event::Event) -> Result<Option<Option<Action>>> {
+        let event::Event::Key(event) = ev else {
+            return Ok(None);
+        };
+        let keymap = match self.mode {
+            Mode::Normal => &self.normal_keys,
+            Mode::Insert => &self.insert_keys,
+            _ => return Ok(None),
+        };
+        if self.skip_keymap {
+            return Ok(keymap
+                .get(&[Key::from(event)])
+                .map(|action| Some(action.clone())));
+        }
 
-        if let Some(cmd) = self.waiting_command {
-            self.waiting_command = None;
-            return self.handle_waiting_command(cmd, ev);
-        }
-
-        let action = match ev {
-            event::Event::Key(event) => {
-                let code = event.code;
-                let modifiers = event.modifiers;
-                match code {
-                    event::KeyCode::Char('q') => Some(Action::Quit),
-                    event::KeyCode::Char('u') => Some(Action::Undo),
-                    event::KeyCode::Up | event::KeyCode::Char('k') => Some(Action::MoveUp),
-                    event::KeyCode::Down | event::KeyCode::Char('j') => Some(Action::MoveDown),
-                    event::KeyCode::Left | event::KeyCode::Char('h') => Some(Action::MoveLeft),
-                    event::KeyCode::Right | event::KeyCode::Char('l') => Some(Action::MoveRight),
-                    event::KeyCode::Char('i') => Some(Action::EnterMode(Mode::Insert)),
-                    event::KeyCode::Char('0') | event::KeyCode::Home => {
-                        Some(Action::MoveToLineStart)
-                    }
-                    event::KeyCode::Char('$') | event::KeyCode::End => Some(Action::MoveToLineEnd),
-                    event::KeyCode::Char('b') | event::KeyCode::PageUp => {
-                        if matches!(modifiers, KeyModifiers::CONTROL) {
-                            Some(Action::PageUp)
-                        } else {
-                            None
-                        }
-                    }
-                    event::KeyCode::Char('f') | event::KeyCode::PageDown => {
-                        if matches!(modifiers, KeyModifiers::CONTROL) {
-                            Some(Action::PageDown)
-                        } else {
-                            None
-                        }
-                    }
-                    event::KeyCode::Char('x') => Some(Action::DeleteCharAtCursorPos),
-                    event::KeyCode::Char('d') => Some(Action::SetWaitingCmd('d')),
-                    event::KeyCode::Char('g') => Some(Action::SetWaitingCmd('g')),
-                    _ => None,
+        self.pending_keys.push(Key::from(event));
+        match keymap.lookup(&self.pending_keys) {
+            Lookup::Found(action) => {
+                let action = action.clone();
+                self.pending_keys.clear();
+                Ok(Some(Some(action)))
+            }
+            Lookup::Prefix => Ok(Some(None)),
+            Lookup::None => {
+                let held = std::mem::take(&mut self.pending_keys);
+                if held.len() == 1 {
+                    return Ok(None);
                 }
+
+                self.skip_keymap = true;
+                let replayed = held[..held.len() - 1]
+                    .iter()
+                    .try_for_each(|key| self.process_event(event::Event::Key(key.event())));
+                self.skip_keymap = false;
+                replayed?;
+
+                // the key that broke the sequence may start a binding of its own, or belong to
+                // whatever mode the replayed keys left the editor in
+                self.handle_event(ev.clone()).map(Some)
             }
-            _ => None,
-        };
-        Ok(action)
+        }
     }
 
-    fn handle_insert_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
-        match ev {
-            event::Event::Key(event) => match event.code {
-                event::KeyCode::Esc => Ok(Some(Action::EnterMode(Mode::Normal))),
-                event::KeyCode::Enter => Ok(Some(Action::NewLine)),
-                event::KeyCode::Char(c) => Ok(Some(Action::InsertCharAtCursorPos(c))),
-                _ => Ok(None),
-            },
-            _ => Ok(None),
+    // clicks move the cursor and the wheel scrolls, in the modes that edit the buffer
+    fn handle_mouse_event(&self, mouse: event::MouseEvent) -> Option<Action> {
+        if !matches!(
+            self.mode,
+            Mode::Normal
+                | Mode::Insert
+                | Mode::Replace
+                | Mode::Visual
+                | Mode::VisualLine
+                | Mode::VisualBlock
+        ) {
+            return None;
+        }
+
+        match mouse.kind {
+            event::MouseEventKind::Down(event::MouseButton::Left)
+            | event::MouseEventKind::Drag(event::MouseButton::Left) => {
+                Some(Action::MoveToScreenPosition(mouse.column, mouse.row))
+            }
+            event::MouseEventKind::ScrollDown => Some(Action::ScrollDown(3)),
+            event::MouseEventKind::ScrollUp => Some(Action::ScrollUp(3)),
+            _ => None,
         }
     }
 
-    //TODO I don't think this handlers are ever gonna fail,
-    fn handle_waiting_command(
+    fn handle_normal_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        if let Some((operator, around)) = self.waiting_object.take() {
+            let object = match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char(c) => TextObject::from_key(c, around),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let action = object.map(|object| match operator {
+                'c' => Action::ChangeObject(object),
+                _ => Action::DeleteObject(object),
+            });
+            if action.is_none() {
+                self.pending_count = None;
+            }
+            return Ok(action);
+        }
+
+        if let Some(cmd) = self.waiting_command {
+            self.waiting_command = None;
+            let action = self.handle_waiting_command(cmd, ev)?;
+            if action.is_none() {
+                self.pending_count = None;
+            }
+            return Ok(action);
+        }
+
+        if let event::Event::Key(event) = ev {
+            if let event::KeyCode::Char(c @ '0'..='9') = event.code {
+                // a leading `0` is still the move to the start of the line
+                let leading_zero = c == '0' && self.pending_count.is_none();
+                if event.modifiers.is_empty() && !leading_zero && self.pending_keys.is_empty() {
+                    let digit = c as usize - '0' as usize;
+                    let count = self.pending_count.unwrap_or(0);
+                    self.pending_count = Some(count.saturating_mul(10).saturating_add(digit));
+                    return Ok(None);
+                }
+            }
+        }
+
+        let Some(action) = self.handle_mapped_key(&ev)? else {
+            if matches!(ev, event::Event::Key(_)) {
+                self.pending_count = None;
+            }
+            return Ok(None);
+        };
+
+        // `q` stops a recording rather than starting another
+        Ok(match action {
+            Some(Action::SetWaitingCmd('q')) if self.recording.is_some() => {
+                Some(Action::StopRecording)
+            }
+            action => action,
+        })
+    }
+
+    fn handle_insert_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        if let Some(action) = self.handle_mapped_key(&ev)? {
+            return Ok(action);
+        }
+
+        match ev {
+            event::Event::Key(event) => match event.code {
+                event::KeyCode::Char(c) => Ok(Some(Action::InsertCharAtCursorPos(c))),
+                _ => Ok(None),
+            },
+            event::Event::Paste(text) => Ok(Some(Action::InsertTextAtCursorPos(text))),
+            _ => Ok(None),
+        }
+    }
+
+    fn handle_replace_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        match ev {
+            event::Event::Key(event) => match event.code {
+                event::KeyCode::Esc => Ok(Some(Action::EnterMode(Mode::Normal))),
+                event::KeyCode::Enter => Ok(Some(Action::NewLine)),
+                event::KeyCode::Backspace => Ok(Some(Action::RestoreOverwrittenChar)),
+                event::KeyCode::Char(c) => Ok(Some(Action::OverwriteChar(c))),
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    // motions extend the selection from the anchor to the cursor
+    fn handle_visual_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        let event::Event::Key(event) = ev else {
+            return Ok(None);
+        };
+
+        let action = match event.code {
+            event::KeyCode::Esc | event::KeyCode::Char('v' | 'V') => {
+                Action::EnterMode(Mode::Normal)
+            }
+            event::KeyCode::Up | event::KeyCode::Char('k') => Action::MoveUp,
+            event::KeyCode::Down | event::KeyCode::Char('j') => Action::MoveDown,
+            event::KeyCode::Left | event::KeyCode::Char('h') => Action::MoveLeft,
+            event::KeyCode::Right | event::KeyCode::Char('l') => Action::MoveRight,
+            event::KeyCode::Char('0') | event::KeyCode::Home => Action::MoveToLineStart,
+            event::KeyCode::Char('$') | event::KeyCode::End => Action::MoveToLineEnd,
+            event::KeyCode::Char('w') => Action::MoveWordForward,
+            event::KeyCode::Char('b') => Action::MoveWordBackward,
+            event::KeyCode::Char('e') => Action::MoveWordEnd,
+            event::KeyCode::Char('d') | event::KeyCode::Char('x') => Action::DeleteSelection,
+            event::KeyCode::Char('y') => Action::YankSelection,
+            event::KeyCode::Char(':') => Action::CommandOnSelection,
+            event::KeyCode::Char('I') if matches!(self.mode, Mode::VisualBlock) => {
+                Action::BlockInsert(false)
+            }
+            event::KeyCode::Char('A') if matches!(self.mode, Mode::VisualBlock) => {
+                Action::BlockInsert(true)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(action))
+    }
+
+    fn handle_command_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        match ev {
+            event::Event::Key(event) => match event.code {
+                event::KeyCode::Esc => Ok(Some(Action::EnterMode(Mode::Normal))),
+                event::KeyCode::Enter => Ok(Some(Action::ExecuteCommand)),
+                event::KeyCode::Backspace => Ok(Some(Action::DeleteCommandChar)),
+                event::KeyCode::Char(c) => Ok(Some(Action::InsertCommandChar(c))),
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn handle_finder_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        let event::Event::Key(event) = ev else {
+            return Ok(None);
+        };
+
+        let control = matches!(event.modifiers, KeyModifiers::CONTROL);
+        let action = match event.code {
+            event::KeyCode::Esc => Action::EnterMode(Mode::Normal),
+            event::KeyCode::Enter => Action::FinderOpenSelection,
+            event::KeyCode::Backspace => Action::FinderDeleteChar,
+            event::KeyCode::Down | event::KeyCode::Tab => Action::FinderSelectNext,
+            event::KeyCode::Up | event::KeyCode::BackTab => Action::FinderSelectPrev,
+            event::KeyCode::Char('n') if control => Action::FinderSelectNext,
+            event::KeyCode::Char('p') if control => Action::FinderSelectPrev,
+            event::KeyCode::Char(c) => Action::FinderInsertChar(c),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(action))
+    }
+
+    // keys go straight to the shell, except Ctrl-\ which returns to normal mode
+    fn handle_terminal_event(&mut self, ev: event::Event) -> Result<Option<Action>> {
+        let event::Event::Key(event) = ev else {
+            return Ok(None);
+        };
+
+        let control = matches!(event.modifiers, KeyModifiers::CONTROL);
+        let bytes = match event.code {
+            event::KeyCode::Char('\\') | event::KeyCode::Char('4') if control => {
+                return Ok(Some(Action::EnterMode(Mode::Normal)));
+            }
+            event::KeyCode::Char(c) if control => vec![(c as u8) & 0x1f],
+            event::KeyCode::Char(c) => c.to_string().into_bytes(),
+            event::KeyCode::Enter => vec![b'\r'],
+            event::KeyCode::Backspace => vec![0x7f],
+            event::KeyCode::Tab => vec![b'\t'],
+            event::KeyCode::Esc => vec![0x1b],
+            event::KeyCode::Up => b"\x1b[A".to_vec(),
+            event::KeyCode::Down => b"\x1b[B".to_vec(),
+            event::KeyCode::Right => b"\x1b[C".to_vec(),
+            event::KeyCode::Left => b"\x1b[D".to_vec(),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Action::SendToTerminal(bytes)))
+    }
+
+    //TODO I don't think this handlers are ever gonna fail,
+    fn handle_waiting_command(
         &self,
         cmd: char,
         ev: event::Event,
@@ -332,13 +1746,82 @@ impl Editor {
             'd' => match ev {
                 event::Event::Key(event) => match event.code {
                     event::KeyCode::Char('d') => Some(Action::DeleteCurrentLine),
+                    event::KeyCode::Char(c @ ('i' | 'a')) => {
+                        Some(Action::AwaitTextObject('d', c == 'a'))
+                    }
+                    code => motion(code).map(|m| Action::DeleteMotion(Box::new(m))),
+                },
+                _ => None,
+            },
+            'c' => match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char('c') => Some(Action::ChangeLine),
+                    event::KeyCode::Char(c @ ('i' | 'a')) => {
+                        Some(Action::AwaitTextObject('c', c == 'a'))
+                    }
+                    code => motion(code).map(|m| Action::ChangeMotion(Box::new(m))),
+                },
+                _ => None,
+            },
+            'r' => match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char(c) => Some(Action::ReplaceChar(c)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            'f' | 'F' | 't' | 'T' => match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char(c) => Some(Action::FindChar(
+                        c,
+                        match cmd {
+                            'f' => FindKind::Forward,
+                            'F' => FindKind::Backward,
+                            't' => FindKind::TillForward,
+                            _ => FindKind::TillBackward,
+                        },
+                    )),
+                    _ => None,
+                },
+                _ => None,
+            },
+            '"' => match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char(c) => Some(Action::SelectRegister(c)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            'q' => match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char(c) if c.is_ascii_alphanumeric() => {
+                        Some(Action::StartRecording(c))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            '@' => match ev {
+                event::Event::Key(event) => match event.code {
+                    event::KeyCode::Char(c) if c.is_ascii_alphanumeric() || c == '@' => {
+                        Some(Action::PlayMacro(c))
+                    }
                     _ => None,
                 },
                 _ => None,
             },
-            'g' => match ev {
+            'm' | '`' | '\'' => match ev {
                 event::Event::Key(event) => match event.code {
-                    event::KeyCode::Char('g') => Some(Action::MoveLineToViewportCenter),
+                    event::KeyCode::Char(c @ 'a'..='z') => Some(match cmd {
+                        'm' => Action::SetMark(c),
+                        '`' => Action::JumpToMark(c),
+                        _ => Action::JumpToMarkLine(c),
+                    }),
+                    // the ends of the last visual selection can be jumped to, but not set
+                    event::KeyCode::Char(c @ ('<' | '>')) if cmd != 'm' => Some(match cmd {
+                        '`' => Action::JumpToMark(c),
+                        _ => Action::JumpToMarkLine(c),
+                    }),
                     _ => None,
                 },
                 _ => None,
@@ -350,9 +1833,79 @@ impl Editor {
     }
 
     fn execute(&mut self, action: &Action) {
+        // the selection is remembered as it stands before whatever ends it
+        if matches!(
+            self.mode,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock
+        ) {
+            self.mark_selection();
+        }
+
+        if !action.edits_buffer() {
+            self.execute_action(action);
+            return;
+        }
+
+        // the edits made while typing touch at most the cursor line and the lines either side
+        // of it, so their undo is a copy of those lines from before
+        let len = self.buffer.len();
+        let end = (self.buffer_line() + 2).min(len);
+        let start = self.buffer_line().saturating_sub(1).min(end);
+        let before = self.buffer.lines[start..end].to_vec();
+
+        self.execute_action(action);
+
+        let end = (end + self.buffer.len()).saturating_sub(len);
+        self.record_undo_between(start, end, before);
+    }
+
+    fn execute_action(&mut self, action: &Action) {
         match action {
-            Action::Quit => {}
+            Action::StartRecording(c) => {
+                self.recording = Some((*c, vec![]));
+            }
+            Action::StopRecording => {
+                if let Some((c, mut events)) = self.recording.take() {
+                    // the `q` that stopped the recording isn't part of the macro
+                    events.pop();
+                    self.macros.insert(c, events);
+                }
+            }
+            Action::PlayMacro(c) => {
+                let c = match c {
+                    '@' => match self.last_macro {
+                        Some(c) => c,
+                        None => {
+                            self.show_error(anyhow::anyhow!("No previously used register"));
+                            return;
+                        }
+                    },
+                    c => *c,
+                };
+                let Some(events) = self.macros.get(&c).cloned() else {
+                    return;
+                };
+                // a macro that plays itself would otherwise never stop
+                if self.macro_depth >= MAX_MACRO_DEPTH {
+                    self.show_error(anyhow::anyhow!("Macro recursion too deep"));
+                    return;
+                }
+
+                self.last_macro = Some(c);
+                let count = self.pending_count.take().unwrap_or(1);
+                self.macro_depth += 1;
+                'replay: for _ in 0..count {
+                    for ev in &events {
+                        if let Err(err) = self.process_event(ev.clone()) {
+                            self.show_error(err);
+                            break 'replay;
+                        }
+                    }
+                }
+                self.macro_depth -= 1;
+            }
             Action::MoveUp => {
+                let cell = self.cursor_cell();
                 if self.cy == 0 {
                     // scroll up
                     if self.vtop > 0 {
@@ -361,20 +1914,20 @@ impl Editor {
                 } else {
                     self.cy = self.cy.saturating_sub(1);
                 }
+                self.move_to_cell(cell);
             }
             Action::MoveDown => {
+                let cell = self.cursor_cell();
                 self.cy += 1;
                 if self.cy >= self.vheight() {
                     // scroll if possible
                     self.vtop += 1;
                     self.cy -= 1;
                 }
+                self.move_to_cell(cell);
             }
             Action::MoveLeft => {
                 self.cx = self.cx.saturating_sub(1);
-                if self.cx < self.vleft {
-                    self.cx = self.vleft;
-                }
             }
             Action::MoveRight => {
                 self.cx += 1;
@@ -385,6 +1938,56 @@ impl Editor {
             Action::MoveToLineEnd => {
                 self.cx = self.line_length().saturating_sub(1);
             }
+            Action::MoveWordForward => {
+                let iskeyword = self.options().iskeyword.clone();
+                let next =
+                    self.buffer
+                        .next_word_start(self.cx as usize, self.buffer_line(), &iskeyword);
+                self.move_to(next);
+            }
+            Action::MoveWordBackward => {
+                let iskeyword = self.options().iskeyword.clone();
+                let prev =
+                    self.buffer
+                        .prev_word_start(self.cx as usize, self.buffer_line(), &iskeyword);
+                self.move_to(prev);
+            }
+            Action::MoveWordEnd => {
+                let iskeyword = self.options().iskeyword.clone();
+                let end = self
+                    .buffer
+                    .word_end(self.cx as usize, self.buffer_line(), &iskeyword);
+                self.move_to(end);
+            }
+            Action::FindChar(c, kind) => {
+                self.last_find = Some((*c, *kind));
+                self.find_char(*c, *kind);
+            }
+            Action::RepeatFind => {
+                if let Some((c, kind)) = self.last_find {
+                    self.find_char(c, kind);
+                }
+            }
+            Action::RepeatFindReverse => {
+                if let Some((c, kind)) = self.last_find {
+                    self.find_char(c, kind.reversed());
+                }
+            }
+            Action::MatchBracket => {
+                let pairs = self.options().matchpairs.clone();
+                let partner =
+                    self.buffer
+                        .matching_bracket(self.cx as usize, self.buffer_line(), &pairs);
+                self.move_to(partner);
+            }
+            Action::MoveParagraphForward => {
+                let next = self.buffer.next_paragraph(self.buffer_line());
+                self.move_to(Some(next));
+            }
+            Action::MoveParagraphBackward => {
+                let prev = self.buffer.prev_paragraph(self.buffer_line());
+                self.move_to(Some(prev));
+            }
             Action::PageUp => {
                 if self.vtop > 0 {
                     self.vtop = self.vtop.saturating_sub(self.vheight() as usize);
@@ -395,91 +1998,2633 @@ impl Editor {
                     self.vtop += self.vheight() as usize;
                 }
             }
+            // the text scrolls by half a screen and the cursor moves with it, keeping its row
+            // until the scroll hits the end of the buffer
+            Action::HalfPageDown => {
+                let half = (self.vheight() as usize / 2).max(1);
+                let last = self.buffer.len().saturating_sub(1);
+                let max_vtop = self.buffer.len().saturating_sub(self.vheight() as usize);
+                let line = (self.buffer_line() + half).min(last);
+
+                self.vtop = (self.vtop + half).min(max_vtop).max(self.vtop);
+                self.go_to_line(line);
+            }
+            Action::HalfPageUp => {
+                let half = (self.vheight() as usize / 2).max(1);
+                let line = self.buffer_line().saturating_sub(half);
+
+                self.vtop = self.vtop.saturating_sub(half);
+                self.go_to_line(line);
+            }
+            // the wheel scrolls the text, and the cursor stays on its line until that goes off
+            // screen
+            Action::ScrollDown(n) => {
+                let line = self.buffer_line();
+                let max_vtop = self.buffer.len().saturating_sub(self.vheight() as usize);
+                self.vtop = (self.vtop + n).min(max_vtop).max(self.vtop);
+                self.scroll_keeping_line(line);
+            }
+            Action::ScrollUp(n) => {
+                let line = self.buffer_line();
+                self.vtop = self.vtop.saturating_sub(*n);
+                self.scroll_keeping_line(line);
+            }
+            Action::MoveToScreenPosition(col, row) => {
+                let mut rows = vec![];
+                self.fill_screen_rows(&mut rows);
+                let Some(&(line, left)) = rows.get(*row as usize) else {
+                    return;
+                };
+                let last = self.buffer.len().saturating_sub(1);
+                let cell = col.saturating_sub(self.gutter_width()) + left as u16;
+                self.go_to_line(line.min(last));
+                self.move_to_cell(cell);
+            }
             Action::EnterMode(new_mode) => {
-                self.mode = *new_mode;
+                if matches!(new_mode, Mode::Command | Mode::Search) {
+                    self.command.clear();
+                    self.message = None;
+                }
+                if matches!(
+                    new_mode,
+                    Mode::Visual | Mode::VisualLine | Mode::VisualBlock
+                ) {
+                    self.anchor = (self.buffer_line(), self.cx as usize);
+                }
+                if let Some(block) = self.block_insert.take() {
+                    self.finish_block_insert(block);
+                }
+                self.overwritten.clear();
+                self.finder = None;
+                self.mode = match new_mode {
+                    // typing into a terminal buffer goes to its shell
+                    Mode::Insert if self.buffer.terminal.is_some() => Mode::Terminal,
+                    mode => *mode,
+                };
             }
             Action::InsertCharAtCursorPos(c) => {
+                self.buffer.ensure_line(self.buffer_line());
                 self.buffer.insert(self.cx, self.buffer_line(), *c);
                 self.cx += 1;
             }
+            Action::InsertTab => {
+                // with expandtab, spaces fill up to the next tab stop instead
+                let Options {
+                    expandtab, tabstop, ..
+                } = *self.options();
+                let y = self.buffer_line();
+                self.buffer.ensure_line(y);
+
+                if expandtab {
+                    let width = tabstop - self.cursor_cell() as usize % tabstop;
+                    for _ in 0..width {
+                        self.buffer.insert(self.cx, y, ' ');
+                        self.cx += 1;
+                    }
+                } else {
+                    self.buffer.insert(self.cx, y, '\t');
+                    self.cx += 1;
+                }
+            }
+            Action::InsertTextAtCursorPos(text) => {
+                self.insert_text(text);
+            }
             Action::DeleteCharAtCursorPos => {
+                // the cursor may be past the end after moving from a longer line
+                self.cx = self.cx.min(self.line_length().saturating_sub(1));
                 self.buffer.remove(self.cx, self.buffer_line());
             }
+            Action::DeleteCharBeforeCursor => {
+                let line = self.buffer_line();
+                if self.cx > 0 {
+                    self.cx -= 1;
+                    self.buffer.remove(self.cx, line);
+                } else if line > 0 && line < self.buffer.len() {
+                    // at the start of a line, backspace joins it onto the previous one
+                    let cx = self.buffer.lines[line - 1].chars().count();
+                    self.buffer.join_line(line - 1);
+                    self.shift_marks(line, line + 1, 0);
+                    self.go_to_line(line - 1);
+                    self.cx = cx as u16;
+                }
+            }
             Action::NewLine => {
+                let line = self.buffer_line();
+                let indent = self.auto_indent(line);
+                self.buffer.split_line(self.cx as usize, line);
+                self.shift_marks(line + 1, line + 1, 1);
+                self.buffer.lines[line + 1].insert_str(0, &indent);
+                self.overwritten.clear();
+                self.cx = indent.chars().count() as u16;
+                self.go_to_line(line + 1);
+            }
+            Action::AppendAfterCursor => {
+                self.cx = (self.cx + 1).min(self.line_length());
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
+            Action::AppendAtLineEnd => {
+                self.cx = self.line_length();
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
+            Action::InsertAtFirstNonBlank => {
+                self.cx = self.first_non_blank(self.buffer_line()) as u16;
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
+            Action::OpenLineBelow | Action::OpenLineAbove => {
+                let line = self.buffer_line();
+                let at = match action {
+                    Action::OpenLineBelow => line + 1,
+                    _ => line,
+                };
+
+                // even an empty buffer has a line to open one next to
+                self.buffer.ensure_line(line);
+                let indent = self.auto_indent(line);
+                self.cx = indent.chars().count() as u16;
+                self.apply_edit(Edit::replace(at, at, vec![indent]));
+                self.go_to_line(at);
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
+            Action::DeleteToLineEnd => {
+                self.delete_to_line_end();
+                self.check_bounds();
+            }
+            Action::ReplaceChar(c) => {
+                let y = self.buffer_line();
+                let original = self.buffer.get(y);
+                if self.buffer.replace_char(self.cx as usize, y, *c).is_some() {
+                    self.record_undo(Edit::replace(y, y + 1, original.into_iter().collect()));
+                }
+            }
+            Action::OverwriteChar(c) => {
+                let y = self.buffer_line();
+                self.buffer.ensure_line(y);
+
+                // past the end of the line, typing extends it
+                let original = self.buffer.replace_char(self.cx as usize, y, *c);
+                if original.is_none() {
+                    self.buffer.insert(self.cx, y, *c);
+                }
+                self.overwritten.push(original);
+                self.cx += 1;
+            }
+            Action::RestoreOverwrittenChar => {
+                // backspace only moves left over text that wasn't typed in this replace
+                if self.cx > 0 {
+                    self.cx -= 1;
+                    match self.overwritten.pop() {
+                        Some(Some(original)) => {
+                            self.buffer.replace_char(
+                                self.cx as usize,
+                                self.buffer_line(),
+                                original,
+                            );
+                        }
+                        Some(None) => self.buffer.remove(self.cx, self.buffer_line()),
+                        None => {}
+                    }
+                }
+            }
+            Action::ToggleCase => {
+                // `3~` flips three characters as one change, leaving the cursor after them
+                let y = self.buffer_line();
+                let original = self.buffer.get(y);
+                let count = self.pending_count.take().unwrap_or(1);
+
+                let mut toggled = false;
+                for _ in 0..count {
+                    if !self.buffer.toggle_case(self.cx as usize, y) {
+                        break;
+                    }
+                    toggled = true;
+                    self.cx += 1;
+                }
+                if toggled {
+                    self.record_undo(Edit::replace(y, y + 1, original.into_iter().collect()));
+                }
+            }
+            Action::IndentLines | Action::DedentLines => {
+                // `3>>` shifts three lines, undone together
+                let start = self.buffer_line();
+                let count = self.pending_count.take().unwrap_or(1);
+                let end = (start + count).min(self.buffer.len());
+                let original = self.buffer.lines[start.min(end)..end].to_vec();
+
+                let Options {
+                    expandtab,
+                    shiftwidth,
+                    tabstop,
+                    ..
+                } = *self.options();
+                let indent = match expandtab {
+                    true => " ".repeat(shiftwidth),
+                    false => "\t".to_string(),
+                };
+                for y in start..end {
+                    match action {
+                        Action::IndentLines => self.buffer.indent_line(y, &indent),
+                        _ => self.buffer.dedent_line(y, shiftwidth, tabstop),
+                    }
+                }
+
+                if !original.is_empty() {
+                    self.record_undo(Edit::replace(start, end, original));
+                }
+                self.cx = self.first_non_blank(start) as u16;
+            }
+            Action::ChangeToLineEnd => {
+                self.delete_to_line_end();
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
+            Action::ChangeLine => {
+                // the line stays, just emptied
+                let y = self.buffer_line();
+                self.buffer.ensure_line(y);
+                self.set_register(Register::lines(&self.buffer.lines[y..=y]));
+                self.apply_edit(Edit::replace(y, y + 1, vec![String::new()]));
                 self.cx = 0;
-                self.cy += 1;
+                self.execute(&Action::EnterMode(Mode::Insert));
             }
             Action::SetWaitingCmd(cmd) => {
                 self.waiting_command = Some(*cmd);
             }
+            Action::AwaitTextObject(operator, around) => {
+                self.waiting_object = Some((*operator, *around));
+            }
+            Action::DeleteObject(object) => {
+                if let Some((start, end)) = self.object_span(*object) {
+                    self.delete_span(start, end);
+                }
+            }
+            Action::ChangeObject(object) => {
+                let Some((start, mut end)) = self.object_span(*object) else {
+                    return;
+                };
+                // changing the inside of a block spread over lines leaves an empty line to
+                // type on
+                if end.1 == 0 && end.0 > start.0 {
+                    let line = end.0 - 1;
+                    end = (line, self.buffer.get(line).map_or(0, |l| l.chars().count()));
+                }
+                self.delete_span(start, end);
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
             Action::DeleteCurrentLine => {
+                // `3dd` removes three lines as one edit, so a single undo restores them all
                 let line = self.buffer_line();
-                let contents = self.current_line_contents();
-
-                self.buffer.remove_line(self.buffer_line());
+                let count = self.pending_count.take().unwrap_or(1);
+                let end = (line + count).min(self.buffer.len());
+                self.set_register(Register::lines(&self.buffer.lines[line.min(end)..end]));
+                self.apply_edit(Edit::delete(line, end));
+            }
+            Action::YankLine => {
+                let line = self.buffer_line();
+                let count = self.pending_count.take().unwrap_or(1);
+                let end = (line + count).min(self.buffer.len());
+                self.set_register(Register::lines(&self.buffer.lines[line.min(end)..end]));
+            }
+            Action::SelectRegister(c) => match c {
+                '"' | '+' => self.selected_register = Some(*c),
+                c => self.show_error(anyhow::anyhow!("Invalid register name: {c}")),
+            },
+            Action::RepeatChange => {
+                // a count repeats the whole change, each time with the count it was made with
+                let times = self.pending_count.take().unwrap_or(1);
+                let actions = self.last_change.clone();
 
-                self.undo_actions.push(Action::InsertLineAt(line, contents));
+                for _ in 0..times {
+                    self.pending_count = self.last_change_count;
+                    for action in &actions {
+                        let repeat = match action.repeats_with_count() {
+                            true => self.pending_count.take().unwrap_or(1),
+                            false => 1,
+                        };
+                        for _ in 0..repeat {
+                            self.execute(action);
+                        }
+                    }
+                }
+                self.pending_count = None;
+            }
+            Action::PasteAfter => {
+                let count = self.pending_count.take().unwrap_or(1);
+                self.paste(count, true);
+            }
+            Action::PasteBefore => {
+                let count = self.pending_count.take().unwrap_or(1);
+                self.paste(count, false);
             }
             Action::Undo => {
-                if let Some(undo_action) = self.undo_actions.pop() {
-                    self.execute(&undo_action);
+                if let Some(group) = self.undo_actions.pop() {
+                    let redo = self.apply_undo_group(group);
+                    self.redo_actions.push(redo);
                 }
             }
-            Action::InsertLineAt(y, contents) => {
-                if let Some(contents) = contents {
-                    self.buffer.insert_line(*y, contents.to_string());
-                    self.cy = *y as u16;
+            Action::Redo => {
+                if let Some(group) = self.redo_actions.pop() {
+                    let undo = self.apply_undo_group(group);
+                    self.undo_actions.push(undo);
                 }
             }
+            Action::ApplyEdit(edit) => {
+                self.buffer.apply_edit(edit.clone());
+                self.shift_marks(edit.start, edit.end, edit.lines.len());
+                self.go_to_line(edit.start);
+            }
             Action::MoveLineToViewportCenter => {
-                log!("cy = {}, viewport height = {}", self.cy, self.vheight());
-                let viewport_center = self.vheight() / 2;
-                log!("vcenter = {viewport_center}");
-                let distance_to_center = self.cy as isize - viewport_center as isize;
-                log!("dtocenter = {distance_to_center}");
-
-                // We need to move up
-                if distance_to_center > 0 {
-                    log!("distance to center > 0");
-                    let distance_to_center = distance_to_center.abs() as usize;
-                    log!(
-                        "vtop = {} > distance_to_center = {distance_to_center}?",
-                        self.vtop
-                    );
-                    if self.vtop > distance_to_center {
-                        let new_vtop = self.vtop + distance_to_center;
-                        log!("yes, new vtop {new_vtop}");
-                        self.vtop = new_vtop;
-                        self.cy = viewport_center;
-                    }
-                } else if distance_to_center < 0 {
-                    // if distance < 0 we need to scroll down
-                    let distance_to_center = distance_to_center.abs() as usize;
-                    let new_vtop = self.vtop.saturating_sub(distance_to_center);
-                    let distance_to_go = self.vtop + distance_to_center;
-                    log!(
-                        "buffer len = {} > distance_to_go = {distance_to_go}",
-                        self.buffer.len()
-                    );
-                    if self.buffer.len() > distance_to_go && new_vtop != self.vtop {
-                        log!("yes, new vtop {new_vtop}");
-                        self.vtop = new_vtop;
-                        self.cy = viewport_center;
+                self.scroll_cursor_to_row(self.vheight() as usize / 2);
+            }
+            Action::MoveLineToViewportTop => {
+                self.scroll_cursor_to_row(0);
+            }
+            Action::MoveLineToViewportBottom => {
+                self.scroll_cursor_to_row((self.vheight() as usize).saturating_sub(1));
+            }
+            Action::NextDiagnostic => {
+                let line = self.buffer_line();
+                let next = self.diagnostics.iter().find(|d| d.line > line).cloned();
+                self.jump_to_diagnostic(next);
+            }
+            Action::PrevDiagnostic => {
+                let line = self.buffer_line();
+                let prev = self
+                    .diagnostics
+                    .iter()
+                    .rev()
+                    .find(|d| d.line < line)
+                    .cloned();
+                self.jump_to_diagnostic(prev);
+            }
+            Action::SwitchToAlternateBuffer => {
+                if let Err(err) = self.switch_to_alternate() {
+                    self.show_error(err);
+                }
+            }
+            Action::ShowFileInfo => {
+                // like vim, the line ending is only called out when it isn't the usual one
+                let mut file = format!("\"{}\"", self.buffer.name());
+                if self.buffer.modified {
+                    file.push_str(" [Modified]");
+                }
+                if self.options().readonly {
+                    file.push_str(" [readonly]");
+                }
+                if self.buffer.line_ending == LineEnding::CrLf {
+                    file.push_str(" [dos]");
+                }
+                let line = self.buffer_line() + 1;
+                let len = self.buffer.len();
+
+                self.show_message(match len {
+                    0 => format!("{file} --No lines in buffer--"),
+                    len => format!("{file} line {line} of {len} --{}%--", line * 100 / len),
+                });
+            }
+            Action::SendToTerminal(bytes) => {
+                if let Some(terminal) = &mut self.buffer.terminal {
+                    if let Err(err) = terminal.write(bytes) {
+                        self.show_error(err.into());
                     }
                 }
             }
-        }
-    }
+            Action::Save => {
+                if let Err(err) = self.save(false) {
+                    self.show_error(err);
+                }
+            }
+            Action::GoToLine(line) => {
+                self.push_jump();
+                self.jump_to_line(*line);
+                self.cx = self.first_non_blank(*line) as u16;
+            }
+            Action::JumpBack => {
+                if self.jump_index == 0 {
+                    return;
+                }
+                // leaving the end of the list, the current position goes on it so `Ctrl-i`
+                // can come back
+                if self.jump_index == self.jumplist.len() {
+                    self.jumplist.push((self.buffer_line(), self.cx as usize));
+                }
+                self.jump_index -= 1;
+                self.go_to_jump();
+            }
+            Action::JumpForward => {
+                if self.jump_index + 1 >= self.jumplist.len() {
+                    return;
+                }
+                self.jump_index += 1;
+                self.go_to_jump();
+            }
+            Action::SetMark(c) => {
+                self.marks
+                    .insert(*c, (self.buffer_line(), self.cx as usize));
+            }
+            Action::JumpToMark(c) | Action::JumpToMarkLine(c) => {
+                let Some(&(line, col)) = self.marks.get(c) else {
+                    self.show_error(anyhow::anyhow!("Mark not set"));
+                    return;
+                };
 
-    pub fn cleanup(&mut self) -> anyhow::Result<()> {
-        self.stdout.flush()?;
-        self.stdout.execute(terminal::EnterAlternateScreen)?;
-        terminal::disable_raw_mode()?;
+                let line = line.min(self.buffer.len().saturating_sub(1));
+                self.push_jump();
+                self.jump_to_line(line);
+                self.cx = match action {
+                    Action::JumpToMark(_) => col as u16,
+                    _ => self.first_non_blank(line) as u16,
+                };
+            }
+            Action::GoToFirstLine | Action::GoToLastLine => {
+                // with a count, both go to that line instead
+                let last = self.buffer.len().saturating_sub(1);
+                let line = match (self.pending_count.take(), action) {
+                    (Some(n), _) => n.saturating_sub(1).min(last),
+                    (None, Action::GoToFirstLine) => 0,
+                    (None, _) => last,
+                };
+                self.execute(&Action::GoToLine(line));
+            }
+            Action::DeleteSelection if matches!(self.mode, Mode::VisualBlock) => {
+                let (top, bottom, left, right) = self.block();
+                self.set_register(Register::chars(self.selected_text()));
 
-        Ok(())
-    }
+                // lines too short to reach the block lose what they have of it
+                let lines = (top..=bottom)
+                    .map(|y| {
+                        let line = self.buffer.get(y).unwrap_or_default();
+                        line.chars()
+                            .take(left)
+                            .chain(line.chars().skip(right + 1))
+                            .collect()
+                    })
+                    .collect();
+                self.apply_edit(Edit::replace(top, bottom + 1, lines));
+
+                self.mode = Mode::Normal;
+                self.go_to_line(top);
+                self.cx = left as u16;
+            }
+            Action::DeleteSelection if matches!(self.mode, Mode::VisualLine) => {
+                let ((top, _), (bottom, _)) = self.selection();
+                let end = (bottom + 1).min(self.buffer.len());
+                self.set_register(Register::lines(&self.buffer.lines[top.min(end)..end]));
+                self.apply_edit(Edit::delete(top, end));
+
+                self.mode = Mode::Normal;
+                self.go_to_line(top.min(self.buffer.len().saturating_sub(1)));
+                self.cx = 0;
+            }
+            Action::YankSelection if matches!(self.mode, Mode::VisualLine) => {
+                let ((top, _), (bottom, _)) = self.selection();
+                let end = (bottom + 1).min(self.buffer.len());
+                self.set_register(Register::lines(&self.buffer.lines[top.min(end)..end]));
+
+                self.mode = Mode::Normal;
+                self.go_to_line(top);
+                self.cx = 0;
+            }
+            Action::CommandOnSelection => {
+                self.execute(&Action::EnterMode(Mode::Command));
+                self.command = "'<,'>".to_string();
+            }
+            Action::BlockInsert(append) => {
+                let (top, bottom, left, right) = self.block();
+                let col = if *append { right + 1 } else { left };
+
+                // appending past the end of the first line pads it out to the block
+                let line = self.buffer.get(top).unwrap_or_default();
+                let len = line.chars().count();
+                if *append && len < col {
+                    let padded = line + &" ".repeat(col - len);
+                    self.apply_edit(Edit::replace(top, top + 1, vec![padded]));
+                }
+
+                self.go_to_line(top);
+                self.cx = col.min(self.buffer.get(top).map_or(0, |l| l.chars().count())) as u16;
+                self.execute(&Action::EnterMode(Mode::Insert));
+                self.block_insert = Some(BlockInsert {
+                    top,
+                    bottom,
+                    col,
+                    from: self.cx as usize,
+                    append: *append,
+                });
+            }
+            Action::DeleteSelection => {
+                let (start, (end_line, end_col)) = self.selection();
+                self.delete_span(start, (end_line, end_col + 1));
+                self.mode = Mode::Normal;
+            }
+            Action::DeleteMotion(motion) => {
+                if let Some((start, end)) = self.motion_span(motion) {
+                    self.delete_span(start, end);
+                }
+            }
+            Action::ChangeMotion(motion) => {
+                let (x, y) = (self.cx as usize, self.buffer_line());
+                let line = self.buffer.get(y).unwrap_or_default();
+                let on_word = line.chars().nth(x).is_some_and(|c| !c.is_whitespace());
+
+                // like vim, `cw` on a word changes up to its end, keeping the space after it
+                let span = match **motion {
+                    Action::MoveWordForward if on_word => {
+                        let iskeyword = self.options().iskeyword.clone();
+                        let mut end = (text::word_end(&line, x, &iskeyword).unwrap_or(x), y);
+                        for _ in 1..self.pending_count.take().unwrap_or(1) {
+                            match self.buffer.word_end(end.0, end.1, &iskeyword) {
+                                Some(next) => end = next,
+                                None => break,
+                            }
+                        }
+                        Some(((y, x), (end.1, end.0 + 1)))
+                    }
+                    _ => self.motion_span(motion),
+                };
+
+                if let Some((start, end)) = span {
+                    self.delete_span(start, end);
+                }
+                self.execute(&Action::EnterMode(Mode::Insert));
+            }
+            Action::YankSelection => {
+                let ((start_line, start_col), _) = match self.mode {
+                    Mode::VisualBlock => {
+                        let (top, _, left, _) = self.block();
+                        ((top, left), (top, left))
+                    }
+                    _ => self.selection(),
+                };
+                self.set_register(Register::chars(self.selected_text()));
+
+                self.mode = Mode::Normal;
+                self.go_to_line(start_line);
+                self.cx = start_col as u16;
+            }
+            Action::OpenFinder => {
+                self.open_finder(String::new());
+            }
+            Action::FinderInsertChar(c) => {
+                if let Some(finder) = &mut self.finder {
+                    finder.push(*c);
+                }
+            }
+            Action::FinderDeleteChar => {
+                if let Some(finder) = &mut self.finder {
+                    finder.pop();
+                }
+            }
+            Action::FinderSelectNext => {
+                if let Some(finder) = &mut self.finder {
+                    finder.select_next();
+                }
+            }
+            Action::FinderSelectPrev => {
+                if let Some(finder) = &mut self.finder {
+                    finder.select_prev();
+                }
+            }
+            Action::FinderOpenSelection => {
+                let selection = self
+                    .finder
+                    .take()
+                    .and_then(|finder| finder.selection().map(|s| s.to_string()));
+                self.mode = Mode::Normal;
+
+                match selection {
+                    Some(file) => {
+                        let path = self.resolve_path(&file);
+                        if let Err(err) = self.open_file(path) {
+                            self.show_error(err);
+                        }
+                    }
+                    None => self.show_error(anyhow::anyhow!("No matching files")),
+                }
+            }
+            Action::InsertCommandChar(c) => {
+                self.command.push(*c);
+            }
+            Action::DeleteCommandChar => {
+                // deleting past the `:` leaves command mode, like vim
+                if self.command.pop().is_none() {
+                    self.mode = Mode::Normal;
+                }
+            }
+            Action::ExecuteCommand => {
+                let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+                let input = std::mem::take(&mut self.command);
+
+                if matches!(mode, Mode::Search) {
+                    // an empty search repeats the last one
+                    if !input.is_empty() {
+                        self.search_query = Some(input);
+                    }
+                    self.execute(&Action::SearchNext);
+                } else if let Err(err) = self.execute_command(&input) {
+                    self.show_error(err);
+                }
+            }
+            Action::SearchNext => {
+                let Some(query) = self.search_query.clone() else {
+                    self.show_error(anyhow::anyhow!("No previous regular expression"));
+                    return;
+                };
+
+                let (x, y) = (self.cx as usize, self.buffer_line());
+                match self.buffer.find_next(&query, x, y) {
+                    Some(found) => {
+                        if (found.1, found.0) <= (y, x) {
+                            self.show_message("search hit BOTTOM, continuing at TOP".to_string());
+                        }
+                        self.push_jump();
+                        self.move_to(Some(found));
+                    }
+                    None => self.show_error(anyhow::anyhow!("Pattern not found: {query}")),
+                }
+            }
+            Action::SearchPrev => {
+                let Some(query) = self.search_query.clone() else {
+                    self.show_error(anyhow::anyhow!("No previous regular expression"));
+                    return;
+                };
+
+                let (x, y) = (self.cx as usize, self.buffer_line());
+                match self.buffer.find_prev(&query, x, y) {
+                    Some(found) => {
+                        if (found.1, found.0) >= (y, x) {
+                            self.show_message("search hit TOP, continuing at BOTTOM".to_string());
+                        }
+                        self.push_jump();
+                        self.move_to(Some(found));
+                    }
+                    None => self.show_error(anyhow::anyhow!("Pattern not found: {query}")),
+                }
+            }
+        }
+    }
+
+    fn execute_command(&mut self, input: &str) -> anyhow::Result<()> {
+        match command::parse(input)? {
+            Command::Write {
+                range,
+                path,
+                append,
+                force,
+            } => self.write(range, path, append, force)?,
+            Command::Quit { force } => {
+                if self.buffer.modified && !force {
+                    anyhow::bail!("No write since last change (add ! to override)");
+                }
+                if !force {
+                    self.check_other_buffers()?;
+                }
+                self.quit = true;
+            }
+            Command::WriteQuit {
+                path,
+                force,
+                if_modified,
+            } => {
+                if !if_modified || self.buffer.modified {
+                    self.write(None, path, false, force)?;
+                }
+                if !force {
+                    self.check_other_buffers()?;
+                }
+                self.quit = true;
+            }
+            Command::Edit { path, force } => {
+                if self.buffer.modified && !force {
+                    anyhow::bail!("No write since last change (add ! to override)");
+                }
+                match path {
+                    Some(path) => self.open_file(self.resolve_path(&path))?,
+                    None => self.reload_file()?,
+                }
+            }
+            Command::GoToLine(address) => {
+                let last = self.buffer.len().saturating_sub(1);
+                let line = match address {
+                    Address::Current => self.buffer_line(),
+                    Address::Last => last,
+                    // past the end goes to the last line rather than failing
+                    Address::Line(n) => n.saturating_sub(1).min(last),
+                    Address::Mark(c) => match self.marks.get(&c) {
+                        Some(&(line, _)) => line.min(last),
+                        None => anyhow::bail!("Mark not set"),
+                    },
+                };
+                self.execute(&Action::GoToLine(line));
+            }
+            Command::AlternateBuffer => self.switch_to_alternate()?,
+            Command::NextBuffer => {
+                let count = self.buffers.len() + 1;
+                self.switch_to_buffer((self.buffer_index + 1) % count);
+            }
+            Command::PrevBuffer => {
+                let count = self.buffers.len() + 1;
+                self.switch_to_buffer((self.buffer_index + count - 1) % count);
+            }
+            Command::ListBuffers => {
+                let describe = |n: usize, buffer: &Buffer| {
+                    let flag = match n {
+                        n if n == self.buffer_index => "%",
+                        n if Some(n) == self.alternate => "#",
+                        _ => "",
+                    };
+                    let modified = if buffer.modified { " [+]" } else { "" };
+                    format!("{}{flag} \"{}\"{modified}", n + 1, buffer.name())
+                };
+                let list: Vec<_> = self
+                    .listed_buffers()
+                    .enumerate()
+                    .map(|(n, buffer)| describe(n, buffer))
+                    .collect();
+                self.show_message(list.join("  "));
+            }
+            Command::Terminal(command) => {
+                let shell = std::env::var("SHELL").unwrap_or("/bin/sh".to_string());
+                // like vim, any arguments are a command line for the shell to run
+                let args = match &command {
+                    Some(command) => vec!["-c", command.as_str()],
+                    None => vec![],
+                };
+                let terminal = Terminal::spawn(&shell, &args, self.vwidth(), self.vheight())?;
+
+                self.add_buffer(Buffer::for_terminal(terminal));
+                self.mode = Mode::Terminal;
+            }
+            Command::Find(query) => self.open_finder(query),
+            Command::LoadDiagnostics(path) => {
+                let path = self.cwd.join(path);
+                let output = std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow::anyhow!("Can't read {}: {e}", path.display()))?;
+                let found: Vec<_> = diagnostic::parse_errors(&output)
+                    .into_iter()
+                    .map(|(file, d)| (self.resolve_path(&file), d))
+                    .collect();
+
+                // every buffer gets the diagnostics naming its file, replacing any it had
+                let for_file = |file: &Option<String>| {
+                    let mut diagnostics: Vec<_> = found
+                        .iter()
+                        .filter(|(f, _)| Some(f) == file.as_ref())
+                        .map(|(_, d)| d.clone())
+                        .collect();
+                    diagnostics.sort_by_key(|d| (d.line, d.col));
+                    diagnostics
+                };
+                for view in &mut self.buffers {
+                    view.diagnostics = for_file(&view.buffer.file);
+                }
+                self.set_diagnostics(for_file(&self.buffer.file));
+                self.show_message(format!("{} diagnostics", found.len()));
+            }
+            Command::Pwd => {
+                self.show_message(self.cwd.display().to_string());
+            }
+            Command::Cd(dir) => {
+                let dir = match dir {
+                    Some(dir) => self.cwd.join(dir),
+                    None => std::env::var("HOME").map(PathBuf::from)?,
+                };
+
+                match dir.canonicalize() {
+                    Ok(dir) if dir.is_dir() => self.cwd = dir,
+                    _ => anyhow::bail!("Can't find directory \"{}\"", dir.display()),
+                }
+                self.show_message(self.cwd.display().to_string());
+            }
+            Command::Set(args) => self.set_options(&args, false)?,
+            Command::SetLocal(args) => self.set_options(&args, true)?,
+            Command::Align {
+                range,
+                alignment,
+                width,
+            } => {
+                let (start, end) = self.resolve_range(range)?;
+                let width = match (alignment, width, self.options().textwidth) {
+                    (_, Some(width), _) => width,
+                    (Alignment::Left, None, _) => 0,
+                    (_, None, 0) => 80,
+                    (_, None, textwidth) => textwidth,
+                };
+
+                // an empty buffer has nothing to line up
+                let Some(lines) = self.buffer.lines.get(start..=end) else {
+                    return Ok(());
+                };
+                let lines = lines
+                    .iter()
+                    .map(|line| text::align(line, alignment, width))
+                    .collect();
+                self.apply_edit(Edit::replace(start, end + 1, lines));
+            }
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                global,
+            } => {
+                let (start, end) = self.resolve_range(range)?;
+                let Some(original) = self.buffer.lines.get(start..=end) else {
+                    anyhow::bail!("Pattern not found: {pattern}");
+                };
+
+                let (mut count, mut changed, mut last_changed) = (0, 0, start);
+                let lines: Vec<String> = original
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let matches = match global {
+                            true => line.matches(pattern.as_str()).count(),
+                            false => line.contains(pattern.as_str()) as usize,
+                        };
+                        if matches == 0 {
+                            return line.clone();
+                        }
+                        count += matches;
+                        changed += 1;
+                        last_changed = start + i;
+                        match global {
+                            true => line.replace(pattern.as_str(), &replacement),
+                            false => line.replacen(pattern.as_str(), &replacement, 1),
+                        }
+                    })
+                    .collect();
+                if count == 0 {
+                    anyhow::bail!("Pattern not found: {pattern}");
+                }
+
+                // all the lines change as one edit, so a single undo takes them all back
+                self.apply_edit(Edit::replace(start, end + 1, lines));
+                self.go_to_line(last_changed);
+                self.cx = self.first_non_blank(last_changed) as u16;
+                let plural = |n: usize| if n == 1 { "" } else { "s" };
+                self.show_message(format!(
+                    "{count} substitution{} on {changed} line{}",
+                    plural(count),
+                    plural(changed)
+                ));
+            }
+            Command::Alternate => {
+                let Some(file) = &self.buffer.file else {
+                    anyhow::bail!("No file name");
+                };
+
+                // the candidates sit next to the file, whatever `:cd` has changed since
+                let candidates = alternate::candidates(&self.alternate_rules, file);
+                match candidates
+                    .into_iter()
+                    .filter_map(|path| std::path::absolute(path).ok())
+                    .map(|path| self.resolve_path(&path.display().to_string()))
+                    .find(|path| std::path::Path::new(path).is_file())
+                {
+                    Some(path) => self.open_file(path)?,
+                    None => anyhow::bail!("No alternate file for \"{file}\""),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // the ends of the visual selection in buffer order, as inclusive (line, column) pairs
+    fn selection(&self) -> ((usize, usize), (usize, usize)) {
+        let cursor = (self.buffer_line(), self.cx as usize);
+        if self.anchor <= cursor {
+            (self.anchor, cursor)
+        } else {
+            (cursor, self.anchor)
+        }
+    }
+
+    // remembers the ends of the selection as the `'<` and `'>` marks
+    fn mark_selection(&mut self) {
+        let (start, end) = self.selection();
+        self.marks.insert('<', start);
+        self.marks.insert('>', end);
+    }
+
+    // the lines and columns of a block selection, all inclusive, as (top, bottom, left, right)
+    fn block(&self) -> (usize, usize, usize, usize) {
+        let ((top, _), (bottom, _)) = self.selection();
+        let (anchor, cursor) = (self.anchor.1, self.cx as usize);
+        (top, bottom, anchor.min(cursor), anchor.max(cursor))
+    }
+
+    // the columns of `line` covered by the visual selection, end exclusive
+    fn selected_cols(&self, line: usize) -> Option<(usize, usize)> {
+        if let Mode::VisualBlock = self.mode {
+            let (top, bottom, left, right) = self.block();
+            if line < top || line > bottom {
+                return None;
+            }
+            let len = self.buffer.get(line)?.chars().count();
+            return Some((left.min(len), (right + 1).min(len)));
+        }
+        if let Mode::VisualLine = self.mode {
+            let ((top, _), (bottom, _)) = self.selection();
+            if line < top || line > bottom {
+                return None;
+            }
+            return Some((0, self.buffer.get(line)?.chars().count()));
+        }
+        if !matches!(self.mode, Mode::Visual) {
+            return None;
+        }
+
+        let ((start_line, start_col), (end_line, end_col)) = self.selection();
+        if line < start_line || line > end_line {
+            return None;
+        }
+
+        let len = self.buffer.get(line)?.chars().count();
+        let start = if line == start_line { start_col } else { 0 };
+        let end = if line == end_line { end_col + 1 } else { len };
+
+        Some((start.min(len), end.min(len)))
+    }
+
+    fn selected_text(&self) -> String {
+        let ((start_line, _), (end_line, _)) = self.selection();
+
+        (start_line..=end_line)
+            .filter_map(|y| {
+                let (start, end) = self.selected_cols(y)?;
+                let line = self.buffer.get(y)?;
+                Some(
+                    line.chars()
+                        .skip(start)
+                        .take(end - start)
+                        .collect::<String>(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // the text from `start` up to, but not including, `end`, both as (line, column)
+    fn span_text(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        (start.0..=end.0)
+            .map(|y| {
+                let line = self.buffer.get(y).unwrap_or_default();
+                let from = if y == start.0 { start.1 } else { 0 };
+                let to = if y == end.0 { end.1 } else { usize::MAX };
+                line.chars()
+                    .skip(from)
+                    .take(to.saturating_sub(from))
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // copies what was typed on the first line of a block to the others, as long as the typing
+    // stayed on that line
+    fn finish_block_insert(&mut self, block: BlockInsert) {
+        let (x, y) = (self.cx as usize, self.buffer_line());
+        if y != block.top || x <= block.from || block.bottom == block.top {
+            return;
+        }
+        let line = self.buffer.get(y).unwrap_or_default();
+        let text: String = line.chars().skip(block.from).take(x - block.from).collect();
+
+        let lines = (block.top + 1..=block.bottom)
+            .map(|y| {
+                let line = self.buffer.get(y).unwrap_or_default();
+                let len = line.chars().count();
+                if len < block.col && !block.append {
+                    return line;
+                }
+                let padding = " ".repeat(block.col.saturating_sub(len));
+                let (before, after): (String, String) = (
+                    line.chars().take(block.col).collect(),
+                    line.chars().skip(block.col).collect(),
+                );
+                format!("{before}{padding}{text}{after}")
+            })
+            .collect();
+        self.apply_edit(Edit::replace(block.top + 1, block.bottom + 1, lines));
+    }
+
+    // deletes from `start` up to, but not including, `end` into the register, leaving the
+    // cursor where the text was
+    fn delete_span(&mut self, start: (usize, usize), end: (usize, usize)) {
+        if start >= end {
+            return;
+        }
+        self.set_register(Register::chars(self.span_text(start, end)));
+
+        // what's left of the first and last lines is joined into one
+        let first = self.buffer.get(start.0).unwrap_or_default();
+        let last = self.buffer.get(end.0).unwrap_or_default();
+        let joined = first.chars().take(start.1).chain(last.chars().skip(end.1));
+        self.apply_edit(Edit::replace(start.0, end.0 + 1, vec![joined.collect()]));
+
+        self.go_to_line(start.0);
+        self.cx = start.1 as u16;
+    }
+
+    // the span a text object covers around the cursor, as (line, column) positions with the
+    // end exclusive
+    fn object_span(&self, object: TextObject) -> Option<((usize, usize), (usize, usize))> {
+        let (x, y) = (self.cx as usize, self.buffer_line());
+        match object {
+            TextObject::Word { around } => {
+                let line = self.buffer.get(y)?;
+                let (start, end) = text::word_object(&line, x, around, &self.options().iskeyword)?;
+                Some(((y, start), (y, end)))
+            }
+            TextObject::Quote { quote, around } => {
+                let line = self.buffer.get(y)?;
+                let (start, end) = text::quote_object(&line, x, quote, around)?;
+                Some(((y, start), (y, end)))
+            }
+            TextObject::Pair {
+                open,
+                close,
+                around,
+            } => self.buffer.pair_object(x, y, open, close, around),
+        }
+    }
+
+    // the text an operator's motion covers, as (line, column) positions with the end
+    // exclusive. The cursor is left where it was
+    fn motion_span(&mut self, motion: &Action) -> Option<((usize, usize), (usize, usize))> {
+        let (vtop, cy, cx) = (self.vtop, self.cy, self.cx);
+        let start = (self.buffer_line(), self.cx as usize);
+        // where the last of the motions started
+        let mut last = start;
+        for _ in 0..self.pending_count.take().unwrap_or(1) {
+            last = (self.buffer_line(), self.cx as usize);
+            self.execute(motion);
+        }
+        let mut end = (self.buffer_line(), self.cx as usize);
+        (self.vtop, self.cy, self.cx) = (vtop, cy, cx);
+
+        match motion {
+            // `e` and `$` take the character they land on too
+            Action::MoveWordEnd | Action::MoveToLineEnd => end.1 += 1,
+            // like vim, when the last word `w` moves over ends its line, the change stops at
+            // the end of that line rather than taking in the next one
+            Action::MoveWordForward if end.0 > last.0 || end <= last => {
+                end = (last.0, self.buffer.get(last.0)?.chars().count());
+            }
+            _ => {}
+        }
+
+        Some(if end < start {
+            (end, start)
+        } else {
+            (start, end)
+        })
+    }
+
+    fn find_char(&mut self, c: char, kind: FindKind) {
+        let line = self.buffer.get(self.buffer_line()).unwrap_or_default();
+        if let Some(x) = text::find_char(&line, self.cx as usize, c, kind) {
+            self.cx = x as u16;
+        }
+    }
+
+    // moves the cursor to a (column, line) position, staying put when there's nowhere to go
+    fn move_to(&mut self, position: Option<(usize, usize)>) {
+        if let Some((x, y)) = position {
+            self.go_to_line(y);
+            self.cx = x as u16;
+        }
+    }
+
+    // after `vtop` changed, puts the cursor back on `line`, or the nearest line still on screen
+    fn scroll_keeping_line(&mut self, line: usize) {
+        // the cursor is kept out of the `scrolloff` margins, or check_bounds would scroll back
+        let scrolloff = self.scrolloff();
+        let last = self.buffer.len().saturating_sub(1);
+        let bottom = self.vtop + self.vheight().saturating_sub(1) as usize;
+        let top = match self.vtop {
+            0 => 0,
+            vtop => vtop + scrolloff,
+        };
+        let bottom = match bottom >= last {
+            true => bottom,
+            false => bottom.saturating_sub(scrolloff),
+        };
+        self.cy = (line.clamp(top, bottom.max(top)) - self.vtop) as u16;
+    }
+
+    // records the cursor position before a big motion. Jumping from the middle of the list
+    // drops the positions after it
+    fn push_jump(&mut self) {
+        self.jumplist.truncate(self.jump_index);
+        self.jumplist.push((self.buffer_line(), self.cx as usize));
+        if self.jumplist.len() > JUMPLIST_SIZE {
+            self.jumplist.remove(0);
+        }
+        self.jump_index = self.jumplist.len();
+    }
+
+    fn go_to_jump(&mut self) {
+        let (line, col) = self.jumplist[self.jump_index];
+        let line = line.min(self.buffer.len().saturating_sub(1));
+        self.jump_to_line(line);
+        self.cx = col as u16;
+    }
+
+    fn jump_to_diagnostic(&mut self, diagnostic: Option<Diagnostic>) {
+        if let Some(d) = diagnostic {
+            self.go_to_line(d.line);
+            self.cx = d.col as u16;
+        }
+    }
+
+    fn cleanup(&mut self) -> anyhow::Result<()> {
+        self.stdout.flush()?;
+        self.stdout.execute(event::DisableBracketedPaste)?;
+        self.stdout.execute(event::DisableMouseCapture)?;
+        self.stdout.execute(terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+
+        Ok(())
+    }
+
+    // turns a command's line range into 0-based inclusive buffer lines, defaulting to the
+    // cursor line
+    fn resolve_range(&self, range: Option<Range>) -> anyhow::Result<(usize, usize)> {
+        let last = self.buffer.len().saturating_sub(1);
+        let resolve = |address| match address {
+            Address::Current => Ok(self.buffer_line()),
+            Address::Last => Ok(last),
+            Address::Line(n) if n >= 1 && n <= last + 1 => Ok(n - 1),
+            Address::Line(_) => anyhow::bail!("Invalid range"),
+            Address::Mark(c) => match self.marks.get(&c) {
+                Some(&(line, _)) => Ok(line.min(last)),
+                None => anyhow::bail!("Mark not set"),
+            },
+        };
+
+        let Some(range) = range else {
+            return Ok((self.buffer_line(), self.buffer_line()));
+        };
+        let (start, end) = (resolve(range.start)?, resolve(range.end)?);
+        if self.buffer.len() == 0 || start > end {
+            anyhow::bail!("Invalid range");
+        }
+
+        Ok((start, end))
+    }
+
+    fn open_finder(&mut self, query: String) {
+        self.finder = Some(Finder::new(&self.cwd, query));
+        self.mode = Mode::Finder;
+    }
+
+    // pulls in whatever the shell printed, following the output while in terminal mode
+    // returns whether the shell printed anything or exited, so the screen needs redrawing
+    fn update_terminal(&mut self) -> bool {
+        let Some(terminal) = &mut self.buffer.terminal else {
+            return false;
+        };
+
+        let bytes = terminal.read();
+        let running = terminal.is_running();
+        if !bytes.is_empty() {
+            terminal.output.append(&mut self.buffer.lines, &bytes);
+        }
+
+        if !running {
+            self.buffer.terminal = None;
+            self.buffer.lines.push("[Process exited]".to_string());
+            if matches!(self.mode, Mode::Terminal) {
+                self.mode = Mode::Normal;
+            }
+        }
+
+        if !bytes.is_empty() || !running {
+            let last = self.buffer.len() - 1;
+            self.go_to_line(last);
+            self.cx = self.buffer.lines[last].chars().count() as u16;
+        }
+
+        !bytes.is_empty() || !running
+    }
+
+    // what `:w` does: saves the buffer, or writes some of its lines to another file
+    fn write(
+        &mut self,
+        range: Option<Range>,
+        path: Option<String>,
+        append: bool,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        if range.is_none() && path.is_none() {
+            return self.save(force);
+        }
+
+        if path.is_none() && self.options().readonly && !force {
+            anyhow::bail!("'readonly' option is set (add ! to override)");
+        }
+
+        if range.is_some() && path.is_none() {
+            anyhow::bail!("Can't write part of the buffer over its own file");
+        }
+
+        let Some(path) = path
+            .map(|path| self.resolve_path(&path))
+            .or_else(|| self.buffer.file.clone())
+        else {
+            anyhow::bail!("No file name");
+        };
+
+        let lines = match range {
+            Some(range) => {
+                let (start, end) = self.resolve_range(Some(range))?;
+                start..end + 1
+            }
+            None => 0..self.buffer.len(),
+        };
+        // fixeol only adds the newline to what's written here; the buffer keeps its own
+        // unless this is the buffer being written to its file
+        let eol = self.buffer.eol || self.options().fixeol;
+        let lines = self.buffer.write_to(&path, lines, append, eol)?;
+        // like vim, writing all of a buffer without a file gives it that file
+        if self.buffer.file.is_none() && range.is_none() && !append {
+            self.buffer.file = Some(path.clone());
+            self.buffer.modified = false;
+        }
+        if self.buffer.file.as_ref() == Some(&path) && range.is_none() && !append {
+            self.buffer.eol = eol;
+        }
+        let verb = if append { "appended" } else { "written" };
+        self.show_message(format!("\"{path}\" {lines}L {verb}"));
+
+        Ok(())
+    }
+
+    fn save(&mut self, force: bool) -> anyhow::Result<()> {
+        if self.options().readonly && !force {
+            anyhow::bail!("'readonly' option is set (add ! to override)");
+        }
+
+        if self.options().fixeol {
+            self.buffer.eol = true;
+        }
+        self.buffer.save()?;
+        self.show_message(format!(
+            "\"{}\" {}L written",
+            self.buffer.name(),
+            self.buffer.len()
+        ));
+
+        if let (true, Some(file)) = (self.options().undofile, &self.buffer.file) {
+            let groups: Vec<Vec<Edit>> = self
+                .undo_actions
+                .iter()
+                .map(|group| {
+                    group
+                        .0
+                        .iter()
+                        .filter_map(|action| match action {
+                            Action::ApplyEdit(edit) => Some(edit.clone()),
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .collect();
+            undofile::save(file, &self.buffer.lines, &groups)?;
+        }
+
+        Ok(())
+    }
+
+    // picks up the undo history saved with the file, when `undofile` is set
+    fn load_undofile(&mut self) {
+        let (true, Some(file)) = (self.options().undofile, &self.buffer.file) else {
+            return;
+        };
+
+        if let Some(groups) = undofile::load(file, &self.buffer.lines) {
+            self.undo_actions = groups
+                .into_iter()
+                .map(|edits| UndoGroup(edits.into_iter().map(Action::ApplyEdit).collect()))
+                .collect();
+        }
+    }
+
+    fn show_message(&mut self, message: String) {
+        self.message = Some((message, MessageKind::Info));
+        self.message_time = Instant::now();
+    }
+
+    fn show_error(&mut self, err: anyhow::Error) {
+        self.message = Some((err.to_string(), MessageKind::Error));
+        self.message_time = Instant::now();
+    }
+
+    // info messages go away on their own after a few seconds; errors wait for a keystroke.
+    // Returns whether a message was cleared
+    fn expire_message(&mut self) -> bool {
+        if matches!(self.message, Some((_, MessageKind::Info)))
+            && self.message_time.elapsed() >= Duration::from_secs(MESSAGE_TIMEOUT)
+        {
+            self.message = None;
+            return true;
+        }
+        false
+    }
+
+    // relative paths are resolved against the editor's working directory, but are kept relative
+    // to the directory the editor was started in so they stay short in the status line
+    fn resolve_path(&self, path: &str) -> String {
+        let path = self.cwd.join(path);
+
+        match std::env::current_dir() {
+            Ok(dir) => path.strip_prefix(dir).unwrap_or(&path),
+            Err(_) => &path,
+        }
+        .display()
+        .to_string()
+    }
+
+    // switches to the file's buffer, adding one to the list when it isn't there yet
+    fn open_file(&mut self, path: String) -> anyhow::Result<()> {
+        let listed = self
+            .listed_buffers()
+            .position(|buffer| buffer.file.as_ref() == Some(&path));
+        if let Some(n) = listed {
+            self.switch_to_buffer(n);
+            return Ok(());
+        }
+
+        self.add_buffer(Buffer::from_file(Some(path))?);
+        self.use_buffer_indent();
+        self.load_undofile();
+
+        Ok(())
+    }
+
+    // reads the current file again, throwing away any changes along with the undo history, but
+    // keeping the cursor where it was
+    fn reload_file(&mut self) -> anyhow::Result<()> {
+        let Some(file) = self.buffer.file.clone() else {
+            anyhow::bail!("No file name");
+        };
+
+        let buffer = Buffer::from_file(Some(file))?;
+        let view = BufferView {
+            vtop: self.vtop,
+            vleft: self.vleft,
+            cx: self.cx,
+            cy: self.cy,
+            ..BufferView::new(buffer)
+        };
+        self.show(view);
+        self.use_buffer_indent();
+        self.load_undofile();
+
+        Ok(())
+    }
+
+    // follows the indentation style detected in the file so new lines don't mix tabs and spaces
+    fn use_buffer_indent(&mut self) {
+        let Some(indent) = self.buffer.indent else {
+            return;
+        };
+
+        match indent {
+            Indent::Tabs => self.buffer.options.expandtab = Some(false),
+            Indent::Spaces(width) => {
+                self.buffer.options.expandtab = Some(true);
+                self.buffer.options.shiftwidth = Some(width);
+            }
+        }
+        self.update_options();
+
+        let options = self.options();
+        self.show_message(if options.expandtab {
+            format!("Indenting with {} spaces", options.shiftwidth)
+        } else {
+            "Indenting with tabs".to_string()
+        });
+    }
+
+    // applies `:set` or `:setlocal` arguments in turn, stopping at the first bad one
+    fn set_options(&mut self, args: &[String], local: bool) -> anyhow::Result<()> {
+        let result = args.iter().try_for_each(|arg| self.set_option(arg, local));
+        self.update_options();
+        result
+    }
+
+    fn set_option(&mut self, arg: &str, local: bool) -> anyhow::Result<()> {
+        if let Some(name) = arg.strip_suffix('?') {
+            self.update_options();
+            self.show_message(self.options().get(name)?);
+        } else if local {
+            self.buffer.options.set(arg)?;
+        } else {
+            self.global_options.set(arg)?;
+            // like vim, this sets the buffer's own value of a local option too, so one
+            // detected from the file doesn't hide the change
+            if LocalOptions::is_local(arg) {
+                self.buffer.options.set(arg)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn switch_to_alternate(&mut self) -> anyhow::Result<()> {
+        let Some(alternate) = self.alternate else {
+            anyhow::bail!("No alternate file");
+        };
+        self.switch_to_buffer(alternate);
+
+        Ok(())
+    }
+
+    // refuses to quit while a buffer other than the one on screen has unsaved changes
+    fn check_other_buffers(&self) -> anyhow::Result<()> {
+        match self.buffers.iter().find(|view| view.buffer.modified) {
+            Some(view) => anyhow::bail!(
+                "No write since last change for buffer \"{}\" (add ! to override)",
+                view.buffer.name()
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // every buffer in the list in order, including the one on screen
+    fn listed_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        let (before, after) = self.buffers.split_at(self.buffer_index);
+        before
+            .iter()
+            .map(|view| &view.buffer)
+            .chain([&self.buffer])
+            .chain(after.iter().map(|view| &view.buffer))
+    }
+
+    // puts a new buffer on screen at the end of the list
+    fn add_buffer(&mut self, buffer: Buffer) {
+        self.buffers.push(BufferView::new(buffer));
+        self.switch_to_buffer(self.buffers.len());
+    }
+
+    // puts the `n`th buffer of the list on screen, leaving the current one in its place
+    fn switch_to_buffer(&mut self, n: usize) {
+        if n == self.buffer_index || n > self.buffers.len() {
+            return;
+        }
+
+        let slot = if n > self.buffer_index { n - 1 } else { n };
+        let view = self.buffers.remove(slot);
+        let previous = self.show(view);
+        let slot = if n < self.buffer_index {
+            self.buffer_index - 1
+        } else {
+            self.buffer_index
+        };
+        self.buffers.insert(slot, previous);
+        self.alternate = Some(self.buffer_index);
+        self.buffer_index = n;
+    }
+
+    // puts the given buffer on screen, returning the one it replaced
+    fn show(&mut self, view: BufferView) -> BufferView {
+        let previous = BufferView {
+            buffer: std::mem::replace(&mut self.buffer, view.buffer),
+            vtop: self.vtop,
+            vleft: self.vleft,
+            cx: self.cx,
+            cy: self.cy,
+            undo_actions: std::mem::replace(&mut self.undo_actions, view.undo_actions),
+            redo_actions: std::mem::replace(&mut self.redo_actions, view.redo_actions),
+            marks: std::mem::replace(&mut self.marks, view.marks),
+            jumplist: std::mem::replace(&mut self.jumplist, view.jumplist),
+            jump_index: std::mem::replace(&mut self.jump_index, view.jump_index),
+            diagnostics: std::mem::replace(&mut self.diagnostics, view.diagnostics),
+        };
+
+        self.vtop = view.vtop;
+        self.vleft = view.vleft;
+        self.cx = view.cx;
+        self.cy = view.cy;
+        self.update_options();
+
+        previous
+    }
+
+    // applies an edit to the buffer, recording its inverse so a single undo reverts it
+    fn apply_edit(&mut self, edit: Edit) {
+        self.shift_marks(edit.start, edit.end, edit.lines.len());
+        let undo = self.buffer.apply_edit(edit);
+        self.record_undo(undo);
+    }
+
+    // keeps marks and diagnostics on their lines when the lines in `start..end` are replaced by
+    // `len` new ones. Those on lines that went away are dropped
+    fn shift_marks(&mut self, start: usize, end: usize, len: usize) {
+        let shift = |line: &mut usize| {
+            if *line >= end {
+                *line = *line + len - (end - start);
+            } else if *line >= start + len {
+                return false;
+            }
+            true
+        };
+        self.marks.retain(|_, (line, _)| shift(line));
+        self.diagnostics.retain_mut(|d| shift(&mut d.line));
+    }
+
+    // a fresh change invalidates anything that was undone before it
+    fn record_undo(&mut self, undo: Edit) {
+        match &mut self.undo_group {
+            Some(group) => group.0.push(Action::ApplyEdit(undo)),
+            None => self
+                .undo_actions
+                .push(UndoGroup(vec![Action::ApplyEdit(undo)])),
+        }
+        self.redo_actions.clear();
+    }
+
+    // records the undo for a change that turned `before` into the lines now in `start..end`,
+    // covering only the lines that actually differ
+    fn record_undo_between(&mut self, start: usize, end: usize, before: Vec<String>) {
+        let after = &self.buffer.lines[start..end];
+        let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let suffix = before[prefix..]
+            .iter()
+            .rev()
+            .zip(after[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if prefix == before.len() && prefix == after.len() {
+            return;
+        }
+
+        let lines = before[prefix..before.len() - suffix].to_vec();
+        self.record_undo(Edit::replace(start + prefix, end - suffix, lines));
+    }
+
+    fn close_undo_group(&mut self) {
+        if let Some(group) = self.undo_group.take() {
+            if !group.0.is_empty() {
+                self.undo_actions.push(group);
+            }
+        }
+    }
+
+    // undoes or redoes a group, returning the group that reverses it
+    fn apply_undo_group(&mut self, group: UndoGroup) -> UndoGroup {
+        let mut inverse = vec![];
+        for action in group.0.into_iter().rev() {
+            if let Action::ApplyEdit(edit) = action {
+                let start = edit.start;
+                self.shift_marks(edit.start, edit.end, edit.lines.len());
+                inverse.push(Action::ApplyEdit(self.buffer.apply_edit(edit)));
+                self.go_to_line(start);
+            }
+        }
+        UndoGroup(inverse)
+    }
+
+    // inserts possibly multi-line text at the cursor as a single undoable change, leaving the
+    // cursor after it
+    fn insert_text(&mut self, text: &str) {
+        let y = self.buffer_line();
+        let original = self.buffer.get(y);
+        let line = original.clone().unwrap_or_default();
+        let (head, tail) = line.split_at(text::byte_offset(&line, self.cx as usize));
+
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let mut lines: Vec<String> = text.split('\n').map(|l| l.to_string()).collect();
+        lines[0].insert_str(0, head);
+        let last = lines.len() - 1;
+        let cx = lines[last].chars().count();
+        lines[last].push_str(tail);
+
+        let first = lines.remove(0);
+        let inserted = lines.len();
+        self.buffer.ensure_line(y);
+        self.buffer.lines[y] = first;
+        self.buffer.insert_lines(y + 1, lines);
+        self.shift_marks(y + 1, y + 1, inserted);
+
+        let undo = Edit::replace(y, y + 1 + inserted, original.into_iter().collect());
+        self.record_undo(undo);
+        self.go_to_line(y + inserted);
+        self.cx = cx as u16;
+    }
+
+    // moves the cursor to a line that may be far away, centering it in the viewport when it
+    // isn't already visible
+    fn jump_to_line(&mut self, line: usize) {
+        let vheight = self.vheight() as usize;
+
+        if line < self.vtop || line >= self.vtop + vheight {
+            self.vtop = line.saturating_sub(vheight / 2);
+        }
+
+        self.cy = (line - self.vtop) as u16;
+    }
+
+    // scrolls so the cursor line is drawn on the given screen row, as far as the top of the
+    // buffer allows, without moving the cursor within the buffer
+    fn scroll_cursor_to_row(&mut self, row: usize) {
+        let line = self.buffer_line();
+        self.vtop = line.saturating_sub(row);
+        self.cy = (line - self.vtop) as u16;
+    }
+
+    // the indentation a new line next to `line` starts with: a copy of that line's with
+    // autoindent, except when the line is blank
+    fn auto_indent(&self, line: usize) -> String {
+        let line = self.buffer.get(line).unwrap_or_default();
+        if !self.options().autoindent || line.trim().is_empty() {
+            return String::new();
+        }
+
+        text::indentation(&line).to_string()
+    }
+
+    fn first_non_blank(&self, line: usize) -> usize {
+        self.buffer
+            .get(line)
+            .and_then(|l| l.chars().position(|c| !c.is_whitespace()))
+            .unwrap_or(0)
+    }
+
+    // removes the text from the cursor to the end of the line into the register
+    fn delete_to_line_end(&mut self) {
+        let y = self.buffer_line();
+        let Some(original) = self.buffer.get(y) else {
+            return;
+        };
+
+        let removed = self.buffer.truncate_line(self.cx as usize, y);
+        if !removed.is_empty() {
+            self.set_register(Register::chars(removed));
+            self.record_undo(Edit::replace(y, y + 1, vec![original]));
+        }
+    }
+
+    // remembers the last change for `.`: a single normal mode change, or everything from
+    // entering insert mode until leaving it
+    fn record_change(&mut self, action: &Action) {
+        match self.mode {
+            Mode::Normal if action.is_repeatable_change() => {
+                self.last_change = vec![action.clone()];
+                self.last_change_count = self.pending_count;
+            }
+            Mode::Insert | Mode::Replace => self.last_change.push(action.clone()),
+            _ => {}
+        }
+    }
+
+    // the unnamed register always keeps the latest yank, and the `+` register also copies it to
+    // the system clipboard
+    fn set_register(&mut self, register: Register) {
+        if self.selected_register == Some('+') {
+            if let Err(err) = clipboard::set(&register.to_clipboard()) {
+                self.show_error(err);
+            }
+        }
+        self.register = register;
+    }
+
+    // reads the selected register, falling back to the unnamed one if the clipboard can't be read
+    fn get_register(&mut self) -> Register {
+        if self.selected_register == Some('+') {
+            match clipboard::get() {
+                Ok(text) => return Register::from_clipboard(text),
+                Err(err) => self.show_error(err),
+            }
+        }
+        self.register.clone()
+    }
+
+    // puts the register's text after or before the cursor `count` times. Whole lines go below
+    // or above the cursor line, with the cursor on the first of them
+    fn paste(&mut self, count: usize, after: bool) {
+        let register = self.get_register();
+        if register.text.is_empty() && !register.linewise {
+            return;
+        }
+
+        if register.linewise {
+            let text = vec![register.text; count].join("\n");
+            let lines = text.split('\n').map(|l| l.to_string()).collect();
+            let line = self.buffer_line();
+            let at = if after && !self.buffer.lines.is_empty() {
+                line + 1
+            } else {
+                line
+            };
+
+            self.apply_edit(Edit::replace(at, at, lines));
+            self.go_to_line(at);
+            self.cx = self.first_non_blank(at) as u16;
+        } else {
+            if after && self.line_length() > 0 {
+                self.cx += 1;
+            }
+            // the cursor ends up on the last pasted character
+            self.insert_text(&register.text.repeat(count));
+            self.cx = self.cx.saturating_sub(1);
+        }
+    }
+
+    // moves the cursor to the given buffer line, scrolling only if it's not visible
+    fn go_to_line(&mut self, line: usize) {
+        let vheight = (self.vheight() as usize).max(1);
+
+        if line < self.vtop {
+            self.vtop = line;
+        } else if line >= self.vtop + vheight {
+            self.vtop = line + 1 - vheight;
+        }
+
+        self.cy = (line - self.vtop) as u16;
+    }
+}
+
+// restores the terminal however the editor goes away, including when `run` bails with an error
+impl Drop for Editor {
+    fn drop(&mut self) {
+        if self.owns_terminal {
+            _ = self.cleanup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use event::{Event, KeyCode, KeyEvent};
+
+    use super::*;
+
+    // an editor on an 80x24 screen that draws nowhere, editing `text`
+    fn editor(text: &str) -> Editor {
+        editor_on(Box::new(std::io::sink()), (80, 24), text)
+    }
+
+    fn editor_on(output: Box<dyn Write>, size: (u16, u16), text: &str) -> Editor {
+        let mut editor = Editor::with_output(output, size).unwrap();
+        editor.open_buffers(vec![Buffer::with_contents(None, text)]);
+        editor
+    }
+
+    // keeps what the editor draws, for tests that look at the screen
+    #[derive(Clone, Default)]
+    struct Screen(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Screen {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Screen {
+        // the text drawn since the last call, without escape sequences
+        fn take_text(&self) -> String {
+            let drawn = String::from_utf8(std::mem::take(&mut *self.0.borrow_mut())).unwrap();
+            let mut text = String::new();
+            let mut chars = drawn.chars();
+            while let Some(c) = chars.next() {
+                if c != '\x1b' {
+                    text.push(c);
+                } else if chars.next() == Some('[') {
+                    chars.find(|c| ('@'..='~').contains(c));
+                }
+            }
+            text
+        }
+    }
+
+    // types `keys` the way the run loop would. Keys that aren't characters are written like
+    // `<esc>`, `<cr>`, `<bs>`, `<tab>`, `<up>` or `<c-r>`, and `<lt>` is a literal `<`
+    fn type_keys(editor: &mut Editor, keys: &str) {
+        let mut chars = keys.chars();
+        while let Some(c) = chars.next() {
+            let key = match c {
+                '<' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+                    special_key(&name)
+                }
+                c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+            };
+            send(editor, Event::Key(key));
+        }
+    }
+
+    fn special_key(name: &str) -> KeyEvent {
+        let code = match name {
+            "esc" => KeyCode::Esc,
+            "cr" => KeyCode::Enter,
+            "bs" => KeyCode::Backspace,
+            "tab" => KeyCode::Tab,
+            "lt" => KeyCode::Char('<'),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            _ => match name.strip_prefix("c-").and_then(|c| c.chars().next()) {
+                Some(c) => return KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL),
+                None => panic!("unknown key <{name}>"),
+            },
+        };
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn send(editor: &mut Editor, ev: Event) {
+        editor.process_event(ev).unwrap();
+        editor.check_bounds();
+    }
+
+    fn click(editor: &mut Editor, column: u16, row: u16) {
+        let mouse = event::MouseEvent {
+            kind: event::MouseEventKind::Down(event::MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+        send(editor, Event::Mouse(mouse));
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.buffer.lines.join("\n")
+    }
+
+    // as (line, column)
+    fn cursor(editor: &Editor) -> (usize, usize) {
+        (editor.buffer_line(), editor.cx as usize)
+    }
+
+    #[test]
+    fn substitution_over_many_lines_undoes_in_one_step() {
+        let mut editor = editor("one\ntwo\nfour");
+
+        type_keys(&mut editor, ":%s/o/0/g<cr>");
+        assert_eq!(text(&editor), "0ne\ntw0\nf0ur");
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "one\ntwo\nfour");
+
+        type_keys(&mut editor, "<c-r>");
+        assert_eq!(text(&editor), "0ne\ntw0\nf0ur");
+    }
+
+    #[test]
+    fn counted_line_delete_undoes_in_one_step() {
+        let mut editor = editor("a\nb\nc\nd");
+
+        type_keys(&mut editor, "j2dd");
+        assert_eq!(text(&editor), "a\nd");
+        assert_eq!(cursor(&editor), (1, 0));
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "a\nb\nc\nd");
+    }
+
+    // the cursor's screen column, leaving out the gutter
+    fn cursor_cell_on_screen(editor: &Editor) -> u16 {
+        editor.cursor_screen_pos().0 - editor.gutter_width()
+    }
+
+    #[test]
+    fn line_end_and_start_go_by_characters_after_a_leading_tab() {
+        let mut editor = editor("\tabc");
+
+        type_keys(&mut editor, "$");
+        assert_eq!(cursor(&editor), (0, 3));
+        assert_eq!(cursor_cell_on_screen(&editor), 10);
+
+        type_keys(&mut editor, "0");
+        assert_eq!(cursor(&editor), (0, 0));
+        assert_eq!(cursor_cell_on_screen(&editor), 0);
+    }
+
+    #[test]
+    fn clicks_after_a_leading_tab_land_on_the_character_drawn_there() {
+        let mut editor = editor("\tabc");
+        let gutter = editor.gutter_width();
+
+        click(&mut editor, gutter + 9, 0);
+        assert_eq!(cursor(&editor), (0, 2));
+
+        // anywhere over the tab's cells is the tab
+        click(&mut editor, gutter + 3, 0);
+        assert_eq!(cursor(&editor), (0, 0));
+    }
+
+    #[test]
+    fn status_line_fits_narrow_terminals() {
+        for width in [1, 5, 10] {
+            let screen = Screen::default();
+            let mut editor = editor_on(Box::new(screen.clone()), (width, 24), "abc");
+
+            editor.draw_status_line().unwrap();
+            editor.stdout.flush().unwrap();
+            let status = screen.take_text();
+            assert!(status.chars().count() <= width as usize, "{status:?}");
+            // the file name and position are the first things dropped
+            assert!(status.starts_with(&" NORMAL "[..(width as usize).min(8)]));
+            assert!(!status.contains("No Name") && !status.contains("1:1"));
+        }
+    }
+
+    #[test]
+    fn terminals_with_no_room_for_text_still_draw() {
+        let mut editor = editor_on(Box::new(std::io::sink()), (20, 1), "a\nb\nc");
+
+        type_keys(&mut editor, "G");
+        assert_eq!(cursor(&editor), (2, 0));
+        type_keys(&mut editor, ":set scrollbar<cr>");
+        editor.draw().unwrap();
+    }
+
+    fn diagnostic_on(line: usize) -> Diagnostic {
+        Diagnostic {
+            line,
+            col: 1,
+            severity: diagnostic::Severity::Error,
+            message: "broken".to_string(),
+        }
+    }
+
+    #[test]
+    fn diagnostics_follow_their_lines_and_their_buffer() {
+        let mut editor = editor("a\nb\nc");
+        editor.set_diagnostics(vec![diagnostic_on(2)]);
+
+        type_keys(&mut editor, "Onew<esc>");
+        assert_eq!(editor.diagnostics[0].line, 3);
+
+        editor.add_buffer(Buffer::with_contents(None, "other"));
+        assert!(editor.diagnostics.is_empty());
+        type_keys(&mut editor, "<c-^>]d");
+        assert_eq!(cursor(&editor), (3, 0));
+
+        type_keys(&mut editor, "dd");
+        assert!(editor.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn set_overrides_the_indent_detected_in_the_file() {
+        let mut editor = editor("fn a() {\n  b\n}");
+        assert_eq!(editor.options().shiftwidth, 2);
+
+        type_keys(&mut editor, ":set sw=8 noexpandtab<cr>");
+        assert_eq!(editor.options().shiftwidth, 8);
+        assert!(!editor.options().expandtab);
+
+        type_keys(&mut editor, ">>");
+        assert_eq!(editor.buffer.lines[0], "\tfn a() {");
+    }
+
+    fn message(editor: &Editor) -> &str {
+        editor.message.as_ref().map_or("", |(message, _)| message)
+    }
+
+    #[test]
+    fn file_info_says_when_the_buffer_is_modified_or_readonly() {
+        let mut editor = editor("a\nb");
+
+        type_keys(&mut editor, "<c-g>");
+        assert_eq!(message(&editor), "\"No Name\" line 1 of 2 --50%--");
+
+        type_keys(&mut editor, "x:setlocal ro<cr><c-g>");
+        assert_eq!(
+            message(&editor),
+            "\"No Name\" [Modified] [readonly] line 1 of 2 --50%--"
+        );
+    }
+
+    #[test]
+    fn an_empty_buffer_keeps_the_cursor_on_line_zero() {
+        let mut editor = editor("");
+        editor.cy = 5;
+        editor.cx = 3;
+
+        editor.check_bounds();
+        assert_eq!(cursor(&editor), (0, 0));
+
+        type_keys(&mut editor, "jjddGx");
+        assert_eq!(cursor(&editor), (0, 0));
+    }
+
+    #[test]
+    fn the_cursor_steps_over_both_cells_of_a_wide_character() {
+        let mut editor = editor("a日b");
+
+        type_keys(&mut editor, "l");
+        assert_eq!(cursor(&editor), (0, 1));
+        assert_eq!(cursor_cell_on_screen(&editor), 1);
+
+        type_keys(&mut editor, "l");
+        assert_eq!(cursor(&editor), (0, 2));
+        assert_eq!(cursor_cell_on_screen(&editor), 3);
+    }
+
+    // a path in the temp directory that's removed again when dropped
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("red-{}-{name}", std::process::id()));
+            Self(path.to_str().unwrap().to_string())
+        }
+
+        fn read(&self) -> String {
+            std::fs::read_to_string(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn x_only_writes_when_there_are_changes() {
+        let mut editor = editor("a");
+
+        type_keys(&mut editor, ":x<cr>");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn wq_writes_to_the_file_it_is_given() {
+        let file = TempFile::new("wq");
+        let mut editor = editor("ab");
+
+        type_keys(&mut editor, &format!("x:wq {}<cr>", file.0));
+        assert_eq!(file.read(), "b");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn a_bang_writes_over_readonly() {
+        let file = TempFile::new("readonly");
+        std::fs::write(&file.0, "a\n").unwrap();
+        let mut editor = editor("");
+        editor.open_file(file.0.clone()).unwrap();
+
+        type_keys(&mut editor, ":setlocal ro<cr>x:w<cr>");
+        assert_eq!(file.read(), "a\n");
+        type_keys(&mut editor, ":w!<cr>");
+        assert_eq!(file.read(), "\n");
+    }
+
+    #[test]
+    fn a_line_delete_count_is_clamped_to_the_lines_left() {
+        let mut editor = editor("a\nb\nc\nd\ne");
+
+        type_keys(&mut editor, "j3dd");
+        assert_eq!(text(&editor), "a\ne");
+        assert_eq!(cursor(&editor), (1, 0));
+
+        type_keys(&mut editor, "9dd");
+        assert_eq!(text(&editor), "a");
+        assert_eq!(cursor(&editor), (0, 0));
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "a\ne");
+    }
+
+    #[test]
+    fn o_opens_a_line_below_the_last_one() {
+        let mut editor = editor("a\nb");
+
+        type_keys(&mut editor, "jox<esc>");
+        assert_eq!(text(&editor), "a\nb\nx");
+        assert_eq!(cursor(&editor), (2, 0));
+
+        type_keys(&mut editor, "ggOy<esc>");
+        assert_eq!(text(&editor), "y\na\nb\nx");
+        assert_eq!(cursor(&editor), (0, 0));
+    }
+
+    #[test]
+    fn undoing_a_delete_of_the_last_line_puts_it_back_at_the_end() {
+        let mut editor = editor("a\nb\nc");
+
+        type_keys(&mut editor, "Gdd");
+        assert_eq!(text(&editor), "a\nb");
+        assert_eq!(cursor(&editor), (1, 0));
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "a\nb\nc");
+        assert_eq!(cursor(&editor).0, 2);
+    }
+
+    #[test]
+    fn x_on_an_empty_line_or_the_last_column_stays_in_the_line() {
+        let mut editor = editor("\nab");
+
+        type_keys(&mut editor, "x");
+        assert_eq!(text(&editor), "\nab");
+
+        type_keys(&mut editor, "j$x");
+        assert_eq!(text(&editor), "\na");
+        assert_eq!(cursor(&editor), (1, 0));
+
+        type_keys(&mut editor, "xx");
+        assert_eq!(text(&editor), "\n");
+    }
+
+    #[test]
+    fn only_insert_mode_puts_the_cursor_past_the_last_character() {
+        let mut editor = editor("abc");
+
+        type_keys(&mut editor, "$l");
+        assert_eq!(cursor(&editor), (0, 2));
+
+        type_keys(&mut editor, "A");
+        assert_eq!(cursor(&editor), (0, 3));
+        type_keys(&mut editor, "d");
+        assert_eq!(text(&editor), "abcd");
+        assert_eq!(cursor(&editor), (0, 4));
+
+        // leaving insert mode steps back onto the last character, like vim
+        type_keys(&mut editor, "<esc>");
+        assert_eq!(cursor(&editor), (0, 3));
+    }
+
+    #[test]
+    fn semicolon_and_comma_repeat_the_last_find() {
+        let mut editor = editor("a,b,c,d,e");
+
+        type_keys(&mut editor, "f,;");
+        assert_eq!(cursor(&editor), (0, 3));
+        type_keys(&mut editor, ";,");
+        assert_eq!(cursor(&editor), (0, 3));
+
+        // a repeated `t` finds the next match rather than staying put before this one
+        type_keys(&mut editor, "0lt,");
+        assert_eq!(cursor(&editor), (0, 2));
+        type_keys(&mut editor, ";");
+        assert_eq!(cursor(&editor), (0, 4));
+    }
+
+    #[test]
+    fn braces_move_between_paragraphs() {
+        let mut editor = editor("a\nb\n\n\nc\n\nd");
+
+        type_keys(&mut editor, "}");
+        assert_eq!(cursor(&editor), (2, 0));
+        // the run of empty lines is a single boundary
+        type_keys(&mut editor, "}");
+        assert_eq!(cursor(&editor), (5, 0));
+        type_keys(&mut editor, "}");
+        assert_eq!(cursor(&editor), (6, 0));
+
+        type_keys(&mut editor, "{");
+        assert_eq!(cursor(&editor), (5, 0));
+        type_keys(&mut editor, "{{");
+        assert_eq!(cursor(&editor), (0, 0));
+    }
+
+    #[test]
+    fn tab_fills_to_the_next_tab_stop_with_expandtab() {
+        let mut editor = editor("");
+
+        type_keys(&mut editor, ":set ts=4 et<cr>i<tab>x<tab>y<esc>");
+        assert_eq!(text(&editor), "    x   y");
+
+        type_keys(&mut editor, ":set noet<cr>0i<tab><esc>");
+        assert_eq!(text(&editor), "\t    x   y");
+    }
+
+    #[test]
+    fn a_leading_tab_is_drawn_to_the_tab_stop_with_the_cursor_after_it() {
+        let screen = Screen::default();
+        let mut editor = editor_on(Box::new(screen.clone()), (20, 3), "\tab");
+
+        type_keys(&mut editor, ":set nonumber<cr>l");
+        editor.draw_viewport().unwrap();
+        assert_eq!(screen.take_text(), format!("{:20}", "        ab"));
+        assert_eq!(editor.buffer.lines[0], "\tab");
+        assert_eq!(editor.cursor_screen_pos(), (8, 0));
+    }
+
+    #[test]
+    fn moving_along_a_long_line_scrolls_the_view_sideways() {
+        let screen = Screen::default();
+        let line: String = ('a'..='z').cycle().take(100).collect();
+        let mut editor = editor_on(Box::new(screen.clone()), (20, 3), &line);
+
+        type_keys(&mut editor, ":set nonumber<cr>");
+        type_keys(&mut editor, &"l".repeat(30));
+        assert_eq!(cursor(&editor), (0, 30));
+        assert_eq!(editor.vleft, 11);
+        assert_eq!(editor.cursor_screen_pos(), (19, 0));
+
+        editor.draw_viewport().unwrap();
+        assert_eq!(screen.take_text(), &line[11..31]);
+
+        type_keys(&mut editor, "0");
+        assert_eq!(editor.vleft, 0);
+    }
+
+    // counts the allocations each thread makes, so a test can measure just its own
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    // SAFETY: everything is passed straight on to the system allocator
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: std::alloc::Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            std::alloc::System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(|n| n.get());
+        f();
+        ALLOCATIONS.with(|n| n.get()) - before
+    }
+
+    // a micro-benchmark of sorts: once the first frame has sized the reused buffers, redrawing
+    // after an edit shouldn't allocate at all
+    #[test]
+    fn drawing_a_frame_after_an_edit_does_not_allocate() {
+        let text = "fn main() {\n\tprintln!(\"hello\");\n}\n".repeat(20);
+        let mut editor = editor(&text);
+        type_keys(&mut editor, ":set list scrollbar<cr>");
+
+        let first = allocations(|| editor.draw_viewport().unwrap());
+        assert!(first > 0);
+
+        type_keys(&mut editor, "jxjdd");
+        assert_eq!(allocations(|| editor.draw_viewport().unwrap()), 0);
+    }
+
+    #[test]
+    fn edits_mark_the_buffer_modified_until_it_is_written() {
+        let file = TempFile::new("modified");
+        std::fs::write(&file.0, "ab\n").unwrap();
+        let mut editor = editor("");
+        editor.open_file(file.0.clone()).unwrap();
+        assert!(!editor.buffer.modified);
+
+        type_keys(&mut editor, "ix<esc>");
+        assert!(editor.buffer.modified);
+        type_keys(&mut editor, ":q<cr>");
+        assert!(!editor.quit);
+
+        type_keys(&mut editor, ":w<cr>");
+        assert!(!editor.buffer.modified);
+
+        type_keys(&mut editor, "x");
+        assert!(editor.buffer.modified);
+        type_keys(&mut editor, ":q!<cr>");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn the_ruler_shows_the_buffer_line_once_scrolled() {
+        let screen = Screen::default();
+        let text = "line\n".repeat(100);
+        let mut editor = editor_on(Box::new(screen.clone()), (40, 10), &text);
+
+        type_keys(&mut editor, "49jll");
+        assert!(editor.vtop > 0);
+        editor.draw_status_line().unwrap();
+        assert!(screen.take_text().ends_with(" 50:3 "));
+    }
+
+    #[test]
+    fn status_line_draws_on_a_tiny_terminal() {
+        for size in [(0, 0), (1, 1), (3, 2)] {
+            let screen = Screen::default();
+            let mut editor = editor_on(Box::new(screen.clone()), size, "a long file name");
+            editor.buffer.file = Some("a/very/long/file/name.rs".to_string());
+
+            editor.draw_status_line().unwrap();
+            let status = screen.take_text();
+            assert!(status.chars().count() <= size.0 as usize);
+            assert_eq!(status.is_empty(), size.0 == 0);
+        }
+    }
+
+    #[test]
+    fn marks_are_jumped_to_across_a_scroll_and_follow_deleted_lines() {
+        let text: String = (0..100).map(|i| format!("  line {i}\n")).collect();
+        let mut editor = editor(&text);
+
+        type_keys(&mut editor, "10jwlma");
+        assert_eq!(cursor(&editor), (10, 3));
+        type_keys(&mut editor, "G");
+        assert!(editor.vtop > 10);
+
+        type_keys(&mut editor, "`a");
+        assert_eq!(cursor(&editor), (10, 3));
+        assert!(editor.vtop <= 10);
+
+        // deleting lines above the mark moves it up with its line
+        type_keys(&mut editor, "gg3dd'a");
+        assert_eq!(cursor(&editor), (7, 2));
+        assert_eq!(editor.buffer.lines[7], "  line 10");
+
+        // and deleting its line drops it
+        type_keys(&mut editor, "dd0`a");
+        assert_eq!(cursor(&editor), (7, 0));
+    }
+
+    #[test]
+    fn an_insert_session_undoes_in_one_step() {
+        let mut editor = editor("ab");
+
+        type_keys(&mut editor, "ixyz<cr>1<bs>2<esc>");
+        assert_eq!(text(&editor), "xyz\n2ab");
+
+        type_keys(&mut editor, "Aend<esc>");
+        assert_eq!(text(&editor), "xyz\n2abend");
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "xyz\n2ab");
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "ab");
+        type_keys(&mut editor, "<c-r>");
+        assert_eq!(text(&editor), "xyz\n2ab");
+    }
+
+    #[test]
+    fn undofile_from_the_config_loads_history_at_startup() {
+        // the history goes beside the file, so keep it out of the shared temp directory
+        let dir = std::env::temp_dir().join(format!("red-{}-undofile-config", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt").display().to_string();
+        std::fs::write(&file, "a\n").unwrap();
+        let mut editor = editor("");
+        type_keys(
+            &mut editor,
+            &format!(":set undofile<cr>:e {file}<cr>x:w<cr>"),
+        );
+
+        let mut config = keymap::Config::default();
+        config.options.push("undofile".to_string());
+        let mut restarted = Editor::with_output(Box::new(std::io::sink()), (80, 24)).unwrap();
+        restarted.use_config(config);
+        restarted.open_buffers(vec![Buffer::from_file(Some(file)).unwrap()]);
+
+        type_keys(&mut restarted, "u");
+        assert_eq!(text(&restarted), "a");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writing_an_unnamed_buffer_gives_it_the_file() {
+        let file = TempFile::new("named");
+        let mut piped = editor("");
+        piped.open_buffers(vec![Buffer::from_reader("piped\n".as_bytes()).unwrap()]);
+
+        type_keys(&mut piped, &format!(":w {}<cr>", file.0));
+        assert_eq!(file.read(), "piped\n");
+        assert_eq!(piped.buffer.file.as_ref(), Some(&file.0));
+        assert!(!piped.buffer.modified);
+
+        // a part of it doesn't, though
+        let part = TempFile::new("part");
+        let mut unnamed = editor("a\nb");
+        type_keys(&mut unnamed, &format!(":1w {}<cr>", part.0));
+        assert_eq!(part.read(), "a\n");
+        assert_eq!(unnamed.buffer.file, None);
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_kept_unless_fixeol_is_set() {
+        let file = TempFile::new("noeol");
+        std::fs::write(&file.0, "a\nb").unwrap();
+        let mut editor = editor("");
+        editor.open_file(file.0.clone()).unwrap();
+
+        type_keys(&mut editor, ":w<cr>");
+        assert_eq!(file.read(), "a\nb");
+
+        // writing to other files doesn't change what the buffer's own file gets
+        let other = TempFile::new("noeol-other");
+        type_keys(
+            &mut editor,
+            &format!(":set fixeol<cr>:w >> {}<cr>", other.0),
+        );
+        assert_eq!(other.read(), "a\nb\n");
+        type_keys(&mut editor, &format!(":1,2w! {}<cr>", other.0));
+        assert_eq!(other.read(), "a\nb\n");
+        type_keys(&mut editor, ":set nofixeol<cr>:w<cr>");
+        assert_eq!(file.read(), "a\nb");
+
+        type_keys(&mut editor, ":set fixeol<cr>:w<cr>");
+        assert_eq!(file.read(), "a\nb\n");
+        // once written with it, the file has its final newline for good
+        type_keys(&mut editor, ":set nofixeol<cr>:w<cr>");
+        assert_eq!(file.read(), "a\nb\n");
+    }
+
+    #[test]
+    fn scrolloff_keeps_lines_around_the_cursor_except_at_the_ends() {
+        // 22 rows of text on an 80x24 screen
+        let mut editor = editor(&"line\n".repeat(100));
+
+        type_keys(&mut editor, &"j".repeat(18));
+        assert_eq!((editor.vtop, editor.cy), (0, 18));
+        type_keys(&mut editor, "j");
+        assert_eq!((editor.vtop, editor.cy), (1, 18));
+
+        type_keys(&mut editor, &"j".repeat(80));
+        assert_eq!((editor.vtop, editor.cy), (78, 21));
+        type_keys(&mut editor, &"k".repeat(18));
+        assert_eq!((editor.vtop, editor.cy), (78, 3));
+        type_keys(&mut editor, "k");
+        assert_eq!((editor.vtop, editor.cy), (77, 3));
+
+        type_keys(&mut editor, "gg");
+        assert_eq!((editor.vtop, editor.cy), (0, 0));
+    }
+
+    #[test]
+    fn the_cursor_moves_through_the_rows_of_a_wrapped_line() {
+        let screen = Screen::default();
+        let line: String = ('a'..='z').collect();
+        let mut editor = editor_on(Box::new(screen.clone()), (13, 5), &format!("{line}\nnext"));
+
+        // a 2-cell gutter leaves 11 cells for text
+        type_keys(&mut editor, ":set wrap<cr>");
+        type_keys(&mut editor, &"l".repeat(12));
+        assert_eq!(cursor(&editor), (0, 12));
+        assert_eq!(editor.cursor_screen_pos(), (3, 1));
+
+        type_keys(&mut editor, "$");
+        assert_eq!(editor.cursor_screen_pos(), (5, 2));
+
+        editor.draw_viewport().unwrap();
+        let drawn = screen.take_text();
+        // continuation rows leave the gutter blank
+        assert_eq!(drawn, "1 abcdefghijk  lmnopqrstuv  wxyz       ");
+
+        type_keys(&mut editor, ":set nowrap<cr>0");
+        assert_eq!(editor.cursor_screen_pos(), (2, 0));
+    }
+
+    #[test]
+    fn d_takes_word_and_line_motions() {
+        let mut words = editor("one two.three four");
+
+        type_keys(&mut words, "dw");
+        assert_eq!(text(&words), "two.three four");
+        type_keys(&mut words, "de");
+        assert_eq!(text(&words), ".three four");
+        type_keys(&mut words, "ld$");
+        assert_eq!(text(&words), ".");
+        assert_eq!(words.register.text, "three four");
+
+        let mut chars = editor("abc");
+        type_keys(&mut chars, "ldldh");
+        assert_eq!(text(&chars), "c");
+    }
+
+    #[test]
+    fn a_counted_dw_only_stops_at_the_line_end_for_the_last_word() {
+        let delete = |keys| {
+            let mut editor = editor("a b\nc d");
+            type_keys(&mut editor, keys);
+            text(&editor)
+        };
+
+        assert_eq!(delete("3dw"), "d");
+        // the last word ends its line, so the next line stays
+        assert_eq!(delete("2dw"), "\nc d");
+        assert_eq!(delete("wdw"), "a \nc d");
+    }
+
+    #[test]
+    fn c_changes_to_the_word_end_or_line_end_in_one_undo() {
+        let mut editor = editor("one two three");
+
+        type_keys(&mut editor, "cwONE<esc>");
+        assert_eq!(text(&editor), "ONE two three");
+        assert_eq!(cursor(&editor), (0, 3));
+
+        type_keys(&mut editor, "wc$end<esc>");
+        assert_eq!(text(&editor), "ONE end");
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "ONE two three");
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "one two three");
+
+        // a count changes through the end of that many words
+        type_keys(&mut editor, "02cwx<esc>");
+        assert_eq!(text(&editor), "x three");
+    }
+
+    #[test]
+    fn text_objects_delete_and_change_around_the_cursor() {
+        let mut editor = editor("f(a, b) \"x y\" z");
+
+        type_keys(&mut editor, "fadi(");
+        assert_eq!(text(&editor), "f() \"x y\" z");
+
+        type_keys(&mut editor, "ci\"q<esc>");
+        assert_eq!(text(&editor), "f() \"q\" z");
+
+        type_keys(&mut editor, "$daw");
+        assert_eq!(text(&editor), "f() \"q\"");
+
+        type_keys(&mut editor, "uu");
+        assert_eq!(text(&editor), "f() \"x y\" z");
+    }
+
+    #[test]
+    fn e_and_a_open_files_relative_to_the_working_directory() {
+        let dir = std::env::temp_dir().join(format!("red-{}-cd", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.h"), "header\n").unwrap();
+        std::fs::write(dir.join("a.c"), "source\n").unwrap();
+        let file = |name: &str| Some(dir.join(name).display().to_string());
+        let mut editor = editor("");
+
+        type_keys(&mut editor, &format!(":cd {}<cr>:e a.h<cr>", dir.display()));
+        assert_eq!(editor.buffer.file, file("a.h"));
+        assert_eq!(text(&editor), "header");
+
+        type_keys(&mut editor, ":A<cr>");
+        assert_eq!(editor.buffer.file, file("a.c"));
+        assert_eq!(text(&editor), "source");
+
+        // the alternate is found next to the file after changing directory again
+        type_keys(&mut editor, ":cd /<cr>:A<cr>");
+        assert_eq!(editor.buffer.file, file("a.h"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_command_line_cursor_counts_cells_not_bytes() {
+        let mut editor = editor("");
+
+        type_keys(&mut editor, ":e café");
+        assert_eq!(editor.terminal_cursor_pos(), (7, 23));
+
+        type_keys(&mut editor, "<esc>/日本");
+        assert_eq!(editor.terminal_cursor_pos(), (5, 23));
+    }
+
+    #[test]
+    fn config_bindings_replace_unbind_and_extend_the_built_in_keys() {
+        let path = std::env::temp_dir().join(format!("red-{}-config.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[normal]\nx = none\ngx = undo\nU = redo\n[insert]\njk = normal_mode\n",
+        )
+        .unwrap();
+        let config = keymap::load(&path, named_action).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut editor = editor("one two\nthree");
+        editor.use_config(config);
+
+        type_keys(&mut editor, "x");
+        assert_eq!(text(&editor), "one two\nthree");
+
+        // a new binding starting with `g` leaves `gg` working
+        type_keys(&mut editor, "dwjgg");
+        assert_eq!(text(&editor), "two\nthree");
+        assert_eq!(cursor(&editor), (0, 0));
+        type_keys(&mut editor, "gx");
+        assert_eq!(text(&editor), "one two\nthree");
+        type_keys(&mut editor, "U");
+        assert_eq!(text(&editor), "two\nthree");
 
-    fn current_line_contents(&self) -> Option<String> {
-        self.buffer.get(self.buffer_line())
+        // keys held towards `jk` are typed as usual once they can't finish it
+        type_keys(&mut editor, "0ijax");
+        assert_eq!(text(&editor), "jaxtwo\nthree");
+        type_keys(&mut editor, "<esc>Ajk");
+        assert_eq!(text(&editor), "jaxtwo\nthree");
+        assert!(matches!(editor.mode, Mode::Normal));
     }
 }
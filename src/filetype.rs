@@ -0,0 +1,31 @@
+use std::path::Path;
+
+// guesses the filetype from the file's extension, or its name for extensionless files
+pub fn detect(path: &str) -> Option<&'static str> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?;
+
+    let filetype = match path.extension().and_then(|e| e.to_str()) {
+        Some("rs") => "rust",
+        Some("c") | Some("h") => "c",
+        Some("cpp") | Some("cc") | Some("hpp") => "cpp",
+        Some("py") => "python",
+        Some("js") => "javascript",
+        Some("ts") => "typescript",
+        Some("go") => "go",
+        Some("md") => "markdown",
+        Some("toml") => "toml",
+        Some("json") => "json",
+        Some("yaml") | Some("yml") => "yaml",
+        Some("html") => "html",
+        Some("css") => "css",
+        Some("sh") | Some("bash") => "sh",
+        _ => match name {
+            "Makefile" | "makefile" => "make",
+            "Dockerfile" => "dockerfile",
+            _ => return None,
+        },
+    };
+
+    Some(filetype)
+}
@@ -0,0 +1,127 @@
+use std::path::Path;
+
+// directories that are never worth searching
+const IGNORED: &[&str] = &[".git", "target", "node_modules"];
+
+// caps the walk so opening the finder in a huge directory (like `~`) stays responsive
+const MAX_FILES: usize = 50_000;
+
+pub struct Finder {
+    files: Vec<String>,
+    pub query: String,
+    pub matches: Vec<String>,
+    pub selected: usize,
+}
+
+impl Finder {
+    pub fn new(root: &Path, query: String) -> Self {
+        let mut files = vec![];
+        list_files(root, root, &mut files);
+        files.sort();
+
+        let mut finder = Self {
+            files,
+            query,
+            matches: vec![],
+            selected: 0,
+        };
+        finder.update();
+
+        finder
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.update();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.update();
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selection(&self) -> Option<&str> {
+        self.matches.get(self.selected).map(|s| s.as_str())
+    }
+
+    fn update(&mut self) {
+        let mut scored: Vec<_> = self
+            .files
+            .iter()
+            .filter_map(|file| score(&self.query, file).map(|score| (score, file)))
+            .collect();
+        // best first; the sort is stable so equal scores stay alphabetical
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+        self.matches = scored.into_iter().map(|(_, file)| file.clone()).collect();
+        self.selected = 0;
+    }
+}
+
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if files.len() >= MAX_FILES {
+            return;
+        }
+
+        let path = entry.path();
+        let name = entry.file_name();
+        if IGNORED.iter().any(|ignored| name == *ignored) {
+            continue;
+        }
+
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => list_files(root, &path, files),
+            Ok(t) if t.is_file() => {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    files.push(relative.display().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// matches when the query's characters appear in order in the candidate (ignoring case), scoring
+// higher for consecutive runs and for matches at the start of a path component or word
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut start = 0;
+
+    for q in query.chars() {
+        let offset = candidate[start..]
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(&q))?;
+        let i = start + offset;
+
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == i) {
+            score += 5;
+        }
+        if i == 0 || matches!(candidate[i - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+
+        previous = Some(i);
+        start = i + 1;
+    }
+
+    // among equally good matches prefer shorter paths
+    Some(score * 1000 - candidate.len() as i64)
+}
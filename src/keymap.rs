@@ -0,0 +1,404 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::options::Options;
+
+// a key as bindings see it. Shift is part of the character for letters and symbols, so it's
+// dropped from their modifiers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn new(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        if let KeyCode::Char(_) = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self { code, modifiers }
+    }
+
+    pub fn event(&self) -> KeyEvent {
+        KeyEvent::new(self.code, self.modifiers)
+    }
+}
+
+impl From<&KeyEvent> for Key {
+    fn from(event: &KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+pub enum Lookup<'a, A> {
+    Found(&'a A),
+    // the keys so far start a longer binding
+    Prefix,
+    None,
+}
+
+// keys bound to `None` are unbound, taking away a binding from a keymap underneath
+pub struct Keymap<A> {
+    bindings: Vec<(Vec<Key>, Option<A>)>,
+}
+
+impl<A> Keymap<A> {
+    // bindings written the way the config file writes them, as ("keys", "action") pairs
+    pub fn from_names(
+        pairs: &[(&str, &str)],
+        action: impl Fn(&str) -> Option<A>,
+    ) -> anyhow::Result<Self> {
+        let bindings = pairs
+            .iter()
+            .map(|&(keys, name)| {
+                let Some(keys) = parse_keys(keys) else {
+                    anyhow::bail!("Invalid keys \"{keys}\"");
+                };
+                let Some(action) = action(name) else {
+                    anyhow::bail!("Unknown action \"{name}\"");
+                };
+                Ok((keys, Some(action)))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { bindings })
+    }
+
+    // puts `over` on top, replacing or unbinding the bindings for the same keys
+    pub fn layer(&mut self, over: Keymap<A>) {
+        self.bindings
+            .retain(|(keys, _)| !over.bindings.iter().any(|(bound, _)| bound == keys));
+        let bound = over
+            .bindings
+            .into_iter()
+            .filter(|(_, action)| action.is_some());
+        self.bindings.splice(0..0, bound);
+    }
+
+    // a longer binding the keys start wins over one for just those keys, since there's no
+    // timeout to tell them apart
+    pub fn lookup(&self, keys: &[Key]) -> Lookup<'_, A> {
+        let mut found = None;
+        for (bound, action) in &self.bindings {
+            if action.is_some() && bound.len() > keys.len() && bound.starts_with(keys) {
+                return Lookup::Prefix;
+            }
+            if bound == keys && found.is_none() {
+                found = Some(action);
+            }
+        }
+        match found {
+            Some(Some(action)) => Lookup::Found(action),
+            _ => Lookup::None,
+        }
+    }
+
+    // the binding for exactly these keys, ignoring any longer ones they start
+    pub fn get(&self, keys: &[Key]) -> Option<&A> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| bound == keys)
+            .and_then(|(_, action)| action.as_ref())
+    }
+}
+
+// the key bindings from the config file, for the modes that can have them, the rules `:A`
+// finds alternate files with as (from, to) patterns, and options as `:set` arguments
+pub struct Config<A> {
+    pub normal: Keymap<A>,
+    pub insert: Keymap<A>,
+    pub alternate: Vec<(String, String)>,
+    pub options: Vec<String>,
+}
+
+impl<A> Default for Config<A> {
+    fn default() -> Self {
+        Self {
+            normal: Keymap { bindings: vec![] },
+            insert: Keymap { bindings: vec![] },
+            alternate: vec![],
+            options: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    Normal,
+    Insert,
+    Alternate,
+    Options,
+}
+
+// `$XDG_CONFIG_HOME/rust-editor/config.toml`, falling back to `~/.config`
+pub fn path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("rust-editor").join("config.toml"))
+}
+
+// reads the bindings in `path`, with `action` turning action names into actions. A missing file
+// has no bindings, but anything in it that doesn't make sense is an error.
+//
+// The file is the small part of TOML it needs: `[normal]` and `[insert]` sections of
+// `"keys" = "action"` lines, where the action `none` unbinds the keys, an `[alternate]` section of `"from" = "to"` lines and an
+// `[options]` section of `name = value` lines, with `#` comments
+pub fn load<A>(path: &Path, action: impl Fn(&str) -> Option<A>) -> anyhow::Result<Config<A>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => anyhow::bail!("Can't read {}: {e}", path.display()),
+    };
+
+    let mut config = Config::default();
+    let mut section = None;
+    for (i, row) in contents.lines().enumerate() {
+        let at = format!("{}:{}", path.display(), i + 1);
+        let row = row.trim();
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = row.strip_prefix('[') {
+            let name = name.split('#').next().unwrap_or_default().trim_end();
+            section = match name.strip_suffix(']').map(str::trim) {
+                Some("normal") => Some(Section::Normal),
+                Some("insert") => Some(Section::Insert),
+                Some("alternate") => Some(Section::Alternate),
+                Some("options") => Some(Section::Options),
+                _ => anyhow::bail!("{at}: Unknown section [{name}"),
+            };
+            continue;
+        }
+
+        let keymap = match section {
+            Some(Section::Normal) => &mut config.normal,
+            Some(Section::Insert) => &mut config.insert,
+            Some(Section::Alternate) => {
+                let (Some(from), Some(to)) = row
+                    .split_once('=')
+                    .map_or((None, None), |(from, to)| (string(from), string(to)))
+                else {
+                    anyhow::bail!("{at}: Expected \"from\" = \"to\"");
+                };
+                if !from.contains("{}") {
+                    anyhow::bail!("{at}: \"{from}\" needs a {{}} for the part both paths share");
+                }
+                config.alternate.push((from.to_string(), to.to_string()));
+                continue;
+            }
+            Some(Section::Options) => {
+                let (Some(name), Some(value)) = row
+                    .split_once('=')
+                    .map_or((None, None), |(name, value)| (string(name), string(value)))
+                else {
+                    anyhow::bail!("{at}: Expected name = value");
+                };
+                // booleans are written like TOML's, and everything else as `:set` takes it
+                let arg = match value {
+                    "true" => name.to_string(),
+                    "false" => format!("no{name}"),
+                    value => format!("{name}={value}"),
+                };
+                if let Err(e) = Options::default().set(&arg) {
+                    anyhow::bail!("{at}: {e}");
+                }
+                config.options.push(arg);
+                continue;
+            }
+            None => anyhow::bail!(
+                "{at}: Settings have to go under [normal], [insert], [alternate] or [options]"
+            ),
+        };
+
+        let Some((keys, name)) = row.split_once('=') else {
+            anyhow::bail!("{at}: Expected \"keys\" = \"action\"");
+        };
+        let (Some(keys), Some(name)) = (string(keys), string(name)) else {
+            anyhow::bail!("{at}: Expected \"keys\" = \"action\"");
+        };
+        let Some(keys) = parse_keys(keys) else {
+            anyhow::bail!("{at}: Invalid keys \"{keys}\"");
+        };
+        let action = match name {
+            "none" => None,
+            name => match action(name) {
+                Some(action) => Some(action),
+                None => anyhow::bail!("{at}: Unknown action \"{name}\""),
+            },
+        };
+        keymap.bindings.push((keys, action));
+    }
+
+    Ok(config)
+}
+
+// a quoted or bare TOML string, without any comment after it
+fn string(s: &str) -> Option<&str> {
+    let s = s.trim();
+    let s = match s.strip_prefix('"') {
+        Some(quoted) => {
+            let (inner, rest) = quoted.split_once('"')?;
+            let rest = rest.trim();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return None;
+            }
+            inner
+        }
+        None => s.split('#').next()?.trim(),
+    };
+    (!s.is_empty()).then_some(s)
+}
+
+// key descriptions are `ctrl-s` or `alt-x` for a key with modifiers, a key name like `esc` or
+// `pageup`, or otherwise a sequence of characters like `dd`
+fn parse_keys(desc: &str) -> Option<Vec<Key>> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = desc;
+    loop {
+        let (modifier, stripped) = if let Some(s) = rest.strip_prefix("ctrl-") {
+            (KeyModifiers::CONTROL, s)
+        } else if let Some(s) = rest.strip_prefix("alt-") {
+            (KeyModifiers::ALT, s)
+        } else if let Some(s) = rest.strip_prefix("shift-") {
+            (KeyModifiers::SHIFT, s)
+        } else {
+            break;
+        };
+        modifiers |= modifier;
+        rest = stripped;
+    }
+
+    if let Some(code) = key_named(rest) {
+        return Some(vec![Key::new(code, modifiers)]);
+    }
+
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(vec![Key::new(KeyCode::Char(c), modifiers)]),
+        // only a single key can have modifiers
+        _ if !modifiers.is_empty() => None,
+        (None, _) => None,
+        _ => Some(
+            rest.chars()
+                .map(|c| Key::new(KeyCode::Char(c), modifiers))
+                .collect(),
+        ),
+    }
+}
+
+fn key_named(name: &str) -> Option<KeyCode> {
+    Some(match name {
+        "esc" => KeyCode::Esc,
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loads `contents` as a config file, with `save` as the only action
+    fn load_str(name: &str, contents: &str) -> anyhow::Result<Config<&'static str>> {
+        let path = std::env::temp_dir().join(format!("red-keymap-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let config = load(&path, |name| (name == "save").then_some("save"));
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn key(c: char) -> Key {
+        Key::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn sections_hold_bindings_and_alternate_rules() {
+        let config = load_str(
+            "sections",
+            "# comment\n\
+             [normal]\n\
+             \"ctrl-s\" = \"save\"\n\
+             gs = save # trailing comment\n\
+             [alternate]\n\
+             \"lib/{}.ex\" = \"test/{}_test.exs\"\n",
+        )
+        .unwrap();
+
+        let ctrl_s = Key::new(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert!(matches!(
+            config.normal.lookup(&[ctrl_s]),
+            Lookup::Found(&"save")
+        ));
+        assert!(matches!(config.normal.lookup(&[key('g')]), Lookup::Prefix));
+        assert!(matches!(config.insert.lookup(&[ctrl_s]), Lookup::None));
+        assert_eq!(
+            config.alternate,
+            [("lib/{}.ex".to_string(), "test/{}_test.exs".to_string())]
+        );
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        let error = |name, contents| load_str(name, contents).err().unwrap().to_string();
+
+        assert!(error("section", "[visual]\n").ends_with(":1: Unknown section [visual]"));
+        assert!(
+            error("action", "[normal]\nx = \"nope\"\n").ends_with(":2: Unknown action \"nope\"")
+        );
+        assert!(error("rule", "[alternate]\na.c = b.c\n").contains(":2: \"a.c\" needs a {}"));
+    }
+
+    #[test]
+    fn options_become_set_arguments() {
+        let config = load_str(
+            "options",
+            "[options]\nundofile = true\nnumber = false\ntabstop = 4\nmatchpairs = \"(:),<:>\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.options,
+            ["undofile", "nonumber", "tabstop=4", "matchpairs=(:),<:>"]
+        );
+        assert!(load_str("bad-option", "[options]\ntabstop = 0\n").is_err());
+    }
+
+    #[test]
+    fn layered_bindings_replace_and_unbind_the_ones_below() {
+        let names = |name: &str| ["delete", "top", "undo"].into_iter().find(|&n| n == name);
+        let defaults = [("x", "delete"), ("gg", "top"), ("u", "undo")];
+        let mut keymap = Keymap::from_names(&defaults, names).unwrap();
+        let config = load_str("layer", "[normal]\nx = save\nu = none\ngs = save\n").unwrap();
+        keymap.layer(config.normal);
+
+        assert!(matches!(keymap.lookup(&[key('x')]), Lookup::Found(&"save")));
+        assert!(matches!(keymap.lookup(&[key('u')]), Lookup::None));
+        assert!(keymap.get(&[key('u')]).is_none());
+        // `g` alone waits for the rest of either binding
+        assert!(matches!(keymap.lookup(&[key('g')]), Lookup::Prefix));
+        assert!(matches!(
+            keymap.lookup(&[key('g'), key('g')]),
+            Lookup::Found(&"top")
+        ));
+        assert!(Keymap::from_names(&[("x", "nope")], names).is_err());
+    }
+}
@@ -6,9 +6,21 @@ use editor::Editor;
 use logger::Logger;
 use once_cell::sync::OnceCell;
 
+mod alternate;
 mod buffer;
+mod clipboard;
+mod command;
+mod diagnostic;
 mod editor;
+mod filetype;
+mod finder;
+mod keymap;
 mod logger;
+mod options;
+mod register;
+mod shell;
+mod text;
+mod undofile;
 
 #[allow(unused)]
 static LOGGER: OnceCell<Logger> = OnceCell::new();
@@ -23,12 +35,77 @@ macro_rules! log {
      };
  }
 
+const USAGE: &str = "usage: red [+line] [file ...]\n       red [+line] -";
+
+// what the command line asks for: the files to open, and a 1-based line to start the first one
+// on. A bare `+` starts on the last line
+struct Args {
+    files: Vec<String>,
+    line: Option<usize>,
+}
+
+// `None` when the usage was asked for
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut parsed = Args {
+        files: vec![],
+        line: None,
+    };
+    let mut options = true;
+
+    for arg in args {
+        match arg.as_str() {
+            "--" if options => options = false,
+            "-h" | "--help" if options => return Ok(None),
+            "+" if options => parsed.line = Some(usize::MAX),
+            _ if options && arg.starts_with('+') => match arg[1..].parse() {
+                Ok(line) => parsed.line = Some(line),
+                Err(_) => return Err(format!("invalid line: {arg}")),
+            },
+            // `-` on its own is stdin, not an option
+            _ if options && arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option: {arg}"))
+            }
+            "-" if parsed.files.iter().any(|file| file == "-") => {
+                return Err("stdin can only be read once".to_string())
+            }
+            _ => parsed.files.push(arg),
+        }
+    }
+
+    Ok(Some(parsed))
+}
+
 fn main() -> anyhow::Result<()> {
-    let file = std::env::args().nth(1);
+    // bad arguments are reported before the terminal is touched
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("red: {err}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
 
-    let buffer = Buffer::from_file(file);
+    // stdin has to be drained before the terminal goes into raw mode
+    let mut buffers = args
+        .files
+        .into_iter()
+        .map(|file| match file.as_str() {
+            "-" => Buffer::from_stdin(),
+            _ => Buffer::from_file(Some(file)),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if buffers.is_empty() {
+        buffers.push(Buffer::from_file(None)?);
+    }
 
-    let mut editor = Editor::new(buffer)?;
+    let mut editor = Editor::new(buffers)?;
+    if let Some(line) = args.line {
+        editor.go_to_start_line(line);
+    }
 
     panic::set_hook(Box::new(|info| {
         _ = stdout().execute(terminal::LeaveAlternateScreen);
@@ -38,6 +115,40 @@ fn main() -> anyhow::Result<()> {
         eprintln!("{}", info);
     }));
 
-    editor.run()?;
-    editor.cleanup()
+    editor.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_args_reads_the_line_and_files() {
+        let args = parse(&["+12", "a.rs", "-", "b.rs"]).unwrap().unwrap();
+        assert_eq!(args.line, Some(12));
+        assert_eq!(args.files, ["a.rs", "-", "b.rs"]);
+
+        let args = parse(&["+"]).unwrap().unwrap();
+        assert_eq!(args.line, Some(usize::MAX));
+        assert!(args.files.is_empty());
+
+        // after `--` everything is a file
+        let args = parse(&["--", "-h", "+3"]).unwrap().unwrap();
+        assert_eq!(args.line, None);
+        assert_eq!(args.files, ["-h", "+3"]);
+
+        assert!(parse(&["a.rs", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_arguments() {
+        assert!(parse(&["+x"]).is_err());
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["-", "a.rs", "-"]).is_err());
+        assert!(parse(&["-", "--", "-"]).is_err());
+    }
 }
@@ -0,0 +1,282 @@
+use anyhow::bail;
+
+use crate::text::KeywordChars;
+
+#[derive(Clone)]
+pub struct Options {
+    pub scrollbar: bool,
+    pub number: bool,
+    pub relativenumber: bool,
+    // 0 means no limit
+    pub textwidth: usize,
+    pub expandtab: bool,
+    pub autoindent: bool,
+    // keeps undo history across sessions in a `.editorundo` directory beside each file
+    pub undofile: bool,
+    // adds a missing newline at the end of the file when writing, rather than keeping the
+    // file as it was
+    pub fixeol: bool,
+    pub shiftwidth: usize,
+    pub tabstop: usize,
+    // how many lines to keep visible above and below the cursor
+    pub scrolloff: usize,
+    pub list: bool,
+    // long lines carry on over the following screen rows instead of scrolling sideways
+    pub wrap: bool,
+    pub showmode: bool,
+    pub filetype: String,
+    pub readonly: bool,
+    pub matchpairs: Vec<(char, char)>,
+    pub iskeyword: KeywordChars,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scrollbar: false,
+            number: true,
+            relativenumber: false,
+            textwidth: 0,
+            expandtab: true,
+            autoindent: false,
+            undofile: false,
+            fixeol: false,
+            shiftwidth: 4,
+            tabstop: 8,
+            scrolloff: 3,
+            list: false,
+            wrap: false,
+            showmode: true,
+            filetype: String::new(),
+            readonly: false,
+            matchpairs: vec![('(', ')'), ('{', '}'), ('[', ']')],
+            iskeyword: KeywordChars::default(),
+        }
+    }
+}
+
+// per-buffer overrides, set with `:setlocal`. Anything left unset falls back to the global value
+#[derive(Default)]
+pub struct LocalOptions {
+    pub textwidth: Option<usize>,
+    pub expandtab: Option<bool>,
+    pub shiftwidth: Option<usize>,
+    pub tabstop: Option<usize>,
+    pub filetype: Option<String>,
+    pub readonly: Option<bool>,
+}
+
+impl Options {
+    // applies a single `:set` argument, e.g. `scrollbar`, `noscrollbar` or `textwidth=80`
+    pub fn set(&mut self, arg: &str) -> anyhow::Result<()> {
+        let (name, enable, value) = parse(arg);
+
+        match name {
+            "scrollbar" => self.scrollbar = parse_bool(name, enable, value)?,
+            "number" | "nu" => self.number = parse_bool(name, enable, value)?,
+            "relativenumber" | "rnu" => self.relativenumber = parse_bool(name, enable, value)?,
+            "textwidth" | "tw" => self.textwidth = parse_number(name, value)?,
+            "expandtab" | "et" => self.expandtab = parse_bool(name, enable, value)?,
+            "autoindent" | "ai" => self.autoindent = parse_bool(name, enable, value)?,
+            "undofile" | "udf" => self.undofile = parse_bool(name, enable, value)?,
+            "fixeol" | "fixendofline" => self.fixeol = parse_bool(name, enable, value)?,
+            "shiftwidth" | "sw" => self.shiftwidth = parse_number(name, value)?,
+            "tabstop" | "ts" => self.tabstop = parse_tabstop(name, value)?,
+            "scrolloff" | "so" => self.scrolloff = parse_number(name, value)?,
+            "list" => self.list = parse_bool(name, enable, value)?,
+            "wrap" => self.wrap = parse_bool(name, enable, value)?,
+            "showmode" | "smd" => self.showmode = parse_bool(name, enable, value)?,
+            "filetype" | "ft" => self.filetype = parse_string(name, value)?,
+            "readonly" | "ro" => self.readonly = parse_bool(name, enable, value)?,
+            "matchpairs" | "mps" => self.matchpairs = parse_pairs(name, value)?,
+            "iskeyword" | "isk" => {
+                self.iskeyword = KeywordChars::parse(&parse_string(name, value)?)?
+            }
+            _ => bail!("Unknown option: {arg}"),
+        }
+
+        Ok(())
+    }
+
+    // formats an option like `:set name?` shows it
+    pub fn get(&self, name: &str) -> anyhow::Result<String> {
+        let bool = |name: &str, enabled: bool| {
+            if enabled {
+                name.to_string()
+            } else {
+                format!("no{name}")
+            }
+        };
+
+        Ok(match name {
+            "scrollbar" => bool("scrollbar", self.scrollbar),
+            "number" | "nu" => bool("number", self.number),
+            "relativenumber" | "rnu" => bool("relativenumber", self.relativenumber),
+            "textwidth" | "tw" => format!("textwidth={}", self.textwidth),
+            "expandtab" | "et" => bool("expandtab", self.expandtab),
+            "autoindent" | "ai" => bool("autoindent", self.autoindent),
+            "undofile" | "udf" => bool("undofile", self.undofile),
+            "fixeol" | "fixendofline" => bool("fixeol", self.fixeol),
+            "shiftwidth" | "sw" => format!("shiftwidth={}", self.shiftwidth),
+            "tabstop" | "ts" => format!("tabstop={}", self.tabstop),
+            "scrolloff" | "so" => format!("scrolloff={}", self.scrolloff),
+            "list" => bool("list", self.list),
+            "wrap" => bool("wrap", self.wrap),
+            "showmode" | "smd" => bool("showmode", self.showmode),
+            "filetype" | "ft" => format!("filetype={}", self.filetype),
+            "readonly" | "ro" => bool("readonly", self.readonly),
+            "matchpairs" | "mps" => format!(
+                "matchpairs={}",
+                self.matchpairs
+                    .iter()
+                    .map(|(open, close)| format!("{open}:{close}"))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "iskeyword" | "isk" => format!("iskeyword={}", self.iskeyword.spec()),
+            _ => bail!("Unknown option: {name}"),
+        })
+    }
+
+    // the options in effect for a buffer: its local settings layered over these global ones
+    pub fn layered(&self, local: &LocalOptions) -> Options {
+        Options {
+            textwidth: local.textwidth.unwrap_or(self.textwidth),
+            expandtab: local.expandtab.unwrap_or(self.expandtab),
+            shiftwidth: local.shiftwidth.unwrap_or(self.shiftwidth),
+            tabstop: local.tabstop.unwrap_or(self.tabstop),
+            filetype: local.filetype.clone().unwrap_or(self.filetype.clone()),
+            readonly: local.readonly.unwrap_or(self.readonly),
+            ..self.clone()
+        }
+    }
+}
+
+impl LocalOptions {
+    // applies a single `:setlocal` argument. Global-only options can't be set per buffer
+    pub fn set(&mut self, arg: &str) -> anyhow::Result<()> {
+        let (name, enable, value) = parse(arg);
+
+        match name {
+            "textwidth" | "tw" => self.textwidth = Some(parse_number(name, value)?),
+            "expandtab" | "et" => self.expandtab = Some(parse_bool(name, enable, value)?),
+            "shiftwidth" | "sw" => self.shiftwidth = Some(parse_number(name, value)?),
+            "tabstop" | "ts" => self.tabstop = Some(parse_tabstop(name, value)?),
+            "filetype" | "ft" => self.filetype = Some(parse_string(name, value)?),
+            "readonly" | "ro" => self.readonly = Some(parse_bool(name, enable, value)?),
+            _ => bail!("Not a buffer-local option: {arg}"),
+        }
+
+        Ok(())
+    }
+
+    // whether a `:set` argument names an option buffers can have their own value of
+    pub fn is_local(arg: &str) -> bool {
+        let (name, ..) = parse(arg);
+        matches!(
+            name,
+            "textwidth"
+                | "tw"
+                | "expandtab"
+                | "et"
+                | "shiftwidth"
+                | "sw"
+                | "tabstop"
+                | "ts"
+                | "filetype"
+                | "ft"
+                | "readonly"
+                | "ro"
+        )
+    }
+}
+
+// splits `name`, `noname` and `name=value` into the name, whether it's enabled, and the value
+fn parse(arg: &str) -> (&str, bool, Option<&str>) {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (arg, None),
+    };
+
+    match name.strip_prefix("no") {
+        Some(name) if value.is_none() => (name, false, None),
+        _ => (name, true, value),
+    }
+}
+
+fn parse_bool(name: &str, enable: bool, value: Option<&str>) -> anyhow::Result<bool> {
+    if value.is_some() {
+        bail!("Invalid argument: {name}");
+    }
+
+    Ok(enable)
+}
+
+fn parse_number(name: &str, value: Option<&str>) -> anyhow::Result<usize> {
+    match value.map(|v| v.parse()) {
+        Some(Ok(n)) => Ok(n),
+        _ => bail!("Number required after =: {name}"),
+    }
+}
+
+fn parse_tabstop(name: &str, value: Option<&str>) -> anyhow::Result<usize> {
+    match parse_number(name, value)? {
+        0 => bail!("Argument must be positive: {name}"),
+        n => Ok(n),
+    }
+}
+
+fn parse_string(name: &str, value: Option<&str>) -> anyhow::Result<String> {
+    match value {
+        Some(value) => Ok(value.to_string()),
+        None => bail!("Argument required: {name}"),
+    }
+}
+
+// parses a pair list like `(:),{:},[:],<:>`
+fn parse_pairs(name: &str, value: Option<&str>) -> anyhow::Result<Vec<(char, char)>> {
+    parse_string(name, value)?
+        .split(',')
+        .map(|pair| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some(open), Some(':'), Some(close), None) if open != close => Ok((open, close)),
+                _ => bail!("Invalid argument: {name}={pair}"),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_parses_flags_numbers_and_lists() {
+        let mut options = Options::default();
+
+        options.set("noexpandtab").unwrap();
+        options.set("tw=72").unwrap();
+        options.set("mps=(:),<:>").unwrap();
+        assert!(!options.expandtab);
+        assert_eq!(options.textwidth, 72);
+        assert_eq!(options.get("matchpairs").unwrap(), "matchpairs=(:),<:>");
+
+        assert!(options.set("ts=0").is_err());
+        assert!(options.set("number=3").is_err());
+        assert!(options.set("bogus").is_err());
+    }
+
+    #[test]
+    fn local_values_layer_over_global_ones() {
+        let global = Options::default();
+        let mut local = LocalOptions::default();
+        local.set("sw=2").unwrap();
+
+        let options = global.layered(&local);
+        assert_eq!(options.shiftwidth, 2);
+        assert_eq!(options.tabstop, global.tabstop);
+        assert!(local.set("scrollbar").is_err());
+        assert!(LocalOptions::is_local("noet") && !LocalOptions::is_local("scrollbar"));
+    }
+}
@@ -0,0 +1,38 @@
+// text that was yanked or deleted, ready to be pasted back
+#[derive(Debug, Clone, Default)]
+pub struct Register {
+    pub text: String,
+    // whole lines paste as new lines above or below the cursor, rather than into the line
+    pub linewise: bool,
+}
+
+impl Register {
+    pub fn chars(text: String) -> Self {
+        Self {
+            text,
+            linewise: false,
+        }
+    }
+
+    pub fn lines(lines: &[String]) -> Self {
+        Self {
+            text: lines.join("\n"),
+            linewise: true,
+        }
+    }
+
+    // the clipboard has no notion of lines, so whole lines are marked with a final newline
+    pub fn to_clipboard(&self) -> String {
+        match self.linewise {
+            true => format!("{}\n", self.text),
+            false => self.text.clone(),
+        }
+    }
+
+    pub fn from_clipboard(text: String) -> Self {
+        match text.strip_suffix('\n') {
+            Some(text) => Self::lines(&[text.to_string()]),
+            None => Self::chars(text),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// a shell running in a pseudo terminal, shown in a buffer. Its output is rendered line by line:
+// escape sequences are dropped rather than emulated
+
+#[derive(Default)]
+enum Escape {
+    #[default]
+    None,
+    Start,
+    Csi,
+    Osc,
+}
+
+#[derive(Default)]
+pub struct Output {
+    escape: Escape,
+}
+
+impl Output {
+    // appends output from the shell to `lines`, whose last line is the one being written to
+    pub fn append(&mut self, lines: &mut Vec<String>, bytes: &[u8]) {
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+
+        for c in String::from_utf8_lossy(bytes).chars() {
+            self.escape = match (&self.escape, c) {
+                (Escape::None, '\x1b') => Escape::Start,
+                (Escape::None, '\n') => {
+                    lines.push(String::new());
+                    Escape::None
+                }
+                (Escape::None, '\x08') => {
+                    if let Some(line) = lines.last_mut() {
+                        line.pop();
+                    }
+                    Escape::None
+                }
+                (Escape::None, c) => {
+                    if let Some(line) = lines.last_mut().filter(|_| !c.is_control() || c == '\t') {
+                        line.push(c);
+                    }
+                    Escape::None
+                }
+                (Escape::Start, '[') => Escape::Csi,
+                (Escape::Start, ']') => Escape::Osc,
+                (Escape::Start, _) => Escape::None,
+                // CSI sequences end with a byte in `@..=~`
+                (Escape::Csi, '@'..='~') => Escape::None,
+                (Escape::Csi, _) => Escape::Csi,
+                // OSC sequences end with BEL (or ST, whose ESC starts a new sequence)
+                (Escape::Osc, '\x07') => Escape::None,
+                (Escape::Osc, '\x1b') => Escape::Start,
+                (Escape::Osc, _) => Escape::Osc,
+            };
+        }
+    }
+}
+
+#[cfg(feature = "terminal")]
+pub use pty::Terminal;
+
+#[cfg(feature = "terminal")]
+mod pty {
+    use std::{
+        fs::File,
+        io::{self, Read, Write},
+        os::{fd::FromRawFd, unix::process::CommandExt},
+        process::{Child, Command, Stdio},
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+
+    use super::Output;
+
+    pub struct Terminal {
+        master: File,
+        child: Child,
+        received: Receiver<Vec<u8>>,
+        pub output: Output,
+    }
+
+    impl Terminal {
+        pub fn spawn(shell: &str, args: &[&str], cols: u16, rows: u16) -> anyhow::Result<Self> {
+            let mut master = 0;
+            let mut slave = 0;
+            let size = libc::winsize {
+                ws_row: rows,
+                ws_col: cols,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+
+            // SAFETY: the out pointers are valid for writes, and null name/termios are allowed
+            let result = unsafe {
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    &size,
+                )
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            // SAFETY: openpty succeeded, so both descriptors are open and owned by us
+            let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+            let mut command = Command::new(shell);
+            command
+                .args(args)
+                .env("TERM", "dumb")
+                .stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave));
+
+            // SAFETY: only async-signal-safe calls are made between fork and exec. The child
+            // gets its own session with the pty as its controlling terminal, so job control
+            // and Ctrl-c work inside the shell
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            let child = command.spawn()?;
+
+            let mut reader = master.try_clone()?;
+            let (sender, received) = mpsc::channel();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while let Ok(n) = reader.read(&mut buf) {
+                    if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            Ok(Self {
+                master,
+                child,
+                received,
+                output: Output::default(),
+            })
+        }
+
+        pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.master.write_all(bytes)
+        }
+
+        // everything the shell printed since the last call
+        pub fn read(&mut self) -> Vec<u8> {
+            self.received.try_iter().flatten().collect()
+        }
+
+        pub fn is_running(&mut self) -> bool {
+            matches!(self.child.try_wait(), Ok(None))
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            _ = self.child.kill();
+            _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(not(feature = "terminal"))]
+pub use stub::Terminal;
+
+#[cfg(not(feature = "terminal"))]
+mod stub {
+    use super::Output;
+
+    pub struct Terminal {
+        pub output: Output,
+    }
+
+    impl Terminal {
+        pub fn spawn(_shell: &str, _args: &[&str], _cols: u16, _rows: u16) -> anyhow::Result<Self> {
+            anyhow::bail!("Built without terminal support, enable the `terminal` feature")
+        }
+
+        pub fn write(&mut self, _bytes: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        pub fn read(&mut self) -> Vec<u8> {
+            vec![]
+        }
+
+        pub fn is_running(&mut self) -> bool {
+            false
+        }
+    }
+}
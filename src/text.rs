@@ -0,0 +1,493 @@
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+// aligns the text of a line within `width` columns. For `Left`, `width` is the indent to use
+pub fn align(line: &str, alignment: Alignment, width: usize) -> String {
+    let text = line.trim();
+    let len = text.chars().count();
+
+    let padding = match alignment {
+        Alignment::Left => width,
+        _ if text.is_empty() => 0,
+        Alignment::Center => width.saturating_sub(len) / 2,
+        Alignment::Right => width.saturating_sub(len),
+    };
+
+    format!("{}{}", " ".repeat(padding), text)
+}
+
+// the byte offset of the character at logical column `col`, or the line's length past its end
+pub fn byte_offset(line: &str, col: usize) -> usize {
+    line.char_indices().nth(col).map_or(line.len(), |(i, _)| i)
+}
+
+// the spaces and tabs a line starts with
+pub fn indentation(line: &str) -> &str {
+    let end = line.len() - line.trim_start_matches([' ', '\t']).len();
+    &line[..end]
+}
+
+// how many cells `c` takes when drawn starting at display cell `cell`
+pub fn char_width(c: char, cell: usize, tabstop: usize) -> usize {
+    match c {
+        '\t' => tabstop - cell % tabstop,
+        c if is_wide(c) => 2,
+        _ => 1,
+    }
+}
+
+// appends the cells of a rendered line from `start` up to `start + width` to `sliced`. A wide
+// character cut by either edge is drawn as spaces for the half that shows
+pub fn slice_cells(line: &str, start: usize, width: usize, sliced: &mut String) {
+    let end = start + width;
+    let mut cell = 0;
+    for c in line.chars() {
+        if cell >= end {
+            break;
+        }
+        let w = if is_wide(c) { 2 } else { 1 };
+        if cell >= start && cell + w <= end {
+            sliced.push(c);
+        } else if cell + w > start {
+            let shown = (cell + w).min(end) - cell.max(start);
+            sliced.extend(std::iter::repeat_n(' ', shown));
+        }
+        cell += w;
+    }
+}
+
+// CJK, Hangul, fullwidth forms and most emoji are drawn two cells wide by terminals
+fn is_wide(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD
+    )
+}
+
+// how many cells the whole line takes on screen
+pub fn display_width(line: &str, tabstop: usize) -> usize {
+    display_col(line, usize::MAX, tabstop)
+}
+
+// the display cell where the character at logical column `col` starts
+pub fn display_col(line: &str, col: usize, tabstop: usize) -> usize {
+    line.chars()
+        .take(col)
+        .fold(0, |cell, c| cell + char_width(c, cell, tabstop))
+}
+
+// the logical column of the character drawn over display cell `cell`, clamped to the line end
+pub fn logical_col(line: &str, cell: usize, tabstop: usize) -> usize {
+    let mut end = 0;
+
+    for (col, c) in line.chars().enumerate() {
+        end += char_width(c, end, tabstop);
+        if cell < end {
+            return col;
+        }
+    }
+
+    line.chars().count()
+}
+
+// appends the line as it's drawn to `rendered`: tabs expand to the next tab stop, and are made
+// visible in list mode
+pub fn render(line: &str, tabstop: usize, list: bool, rendered: &mut String) {
+    let mut cell = 0;
+
+    for c in line.chars() {
+        let width = char_width(c, cell, tabstop);
+        match c {
+            '\t' if list => {
+                rendered.push('>');
+                rendered.extend(std::iter::repeat_n('-', width - 1));
+            }
+            '\t' => rendered.extend(std::iter::repeat_n(' ', width)),
+            c => rendered.push(c),
+        }
+        cell += width;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CharClass {
+    Blank,
+    Keyword,
+    Punctuation,
+}
+
+// like vim, a word is either a run of keyword characters or a run of other non-blank ones
+fn class(c: char, iskeyword: &KeywordChars) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if iskeyword.contains(c) {
+        CharClass::Keyword
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+// the column of the next word after `col` on the line, if there is one
+pub fn next_word_start(line: &str, col: usize, iskeyword: &KeywordChars) -> Option<usize> {
+    let classes: Vec<_> = line.chars().map(|c| class(c, iskeyword)).collect();
+    let current = *classes.get(col)?;
+
+    let mut i = col;
+    while i < classes.len() && classes[i] == current && current != CharClass::Blank {
+        i += 1;
+    }
+    while i < classes.len() && classes[i] == CharClass::Blank {
+        i += 1;
+    }
+
+    (i < classes.len()).then_some(i)
+}
+
+// the column of the word before `col` on the line, if there is one
+pub fn prev_word_start(line: &str, col: usize, iskeyword: &KeywordChars) -> Option<usize> {
+    let classes: Vec<_> = line.chars().map(|c| class(c, iskeyword)).collect();
+
+    let mut i = col.min(classes.len());
+    while i > 0 && classes[i - 1] == CharClass::Blank {
+        i -= 1;
+    }
+    let current = *classes.get(i.checked_sub(1)?)?;
+    while i > 0 && classes[i - 1] == current {
+        i -= 1;
+    }
+
+    Some(i)
+}
+
+// the column of the first word end at or after `from` on the line, if there is one
+pub fn word_end(line: &str, from: usize, iskeyword: &KeywordChars) -> Option<usize> {
+    let classes: Vec<_> = line.chars().map(|c| class(c, iskeyword)).collect();
+
+    let mut i = from;
+    while i < classes.len() && classes[i] == CharClass::Blank {
+        i += 1;
+    }
+    let current = *classes.get(i)?;
+    while i + 1 < classes.len() && classes[i + 1] == current {
+        i += 1;
+    }
+
+    Some(i)
+}
+
+// the columns `iw` or `aw` cover at `col`, end exclusive. `aw` takes in the blanks after the
+// word, or the ones before it when there are none after
+pub fn word_object(
+    line: &str,
+    col: usize,
+    around: bool,
+    iskeyword: &KeywordChars,
+) -> Option<(usize, usize)> {
+    let classes: Vec<_> = line.chars().map(|c| class(c, iskeyword)).collect();
+    let current = *classes.get(col)?;
+    let len = classes.len();
+
+    let mut start = col;
+    while start > 0 && classes[start - 1] == current {
+        start -= 1;
+    }
+    let mut end = col + 1;
+    while end < len && classes[end] == current {
+        end += 1;
+    }
+    if !around {
+        return Some((start, end));
+    }
+
+    // on blanks, `aw` is the blanks and the word after them
+    if current == CharClass::Blank {
+        if let Some(&next) = classes.get(end) {
+            while end < len && classes[end] == next {
+                end += 1;
+            }
+        }
+    } else if classes.get(end) == Some(&CharClass::Blank) {
+        while end < len && classes[end] == CharClass::Blank {
+            end += 1;
+        }
+    } else {
+        while start > 0 && classes[start - 1] == CharClass::Blank {
+            start -= 1;
+        }
+    }
+
+    Some((start, end))
+}
+
+// the columns `i"` or `a"` cover: the first quoted string on the line that ends at or after
+// `col`, end exclusive. `a"` takes in the quotes and the blanks after them
+pub fn quote_object(line: &str, col: usize, quote: char, around: bool) -> Option<(usize, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let quotes: Vec<usize> = (0..chars.len())
+        .filter(|&i| chars[i] == quote && (i == 0 || chars[i - 1] != '\\'))
+        .collect();
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| col <= close)?;
+    if !around {
+        return Some((open + 1, close));
+    }
+
+    let (mut start, mut end) = (open, close + 1);
+    while end < chars.len() && chars[end].is_whitespace() {
+        end += 1;
+    }
+    if end == close + 1 {
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+    }
+
+    Some((start, end))
+}
+
+// what `iw`, `a(`, `i"` and the like pick out around the cursor. `around` also takes in the
+// delimiters, or the blanks next to a word
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextObject {
+    Word {
+        around: bool,
+    },
+    Pair {
+        open: char,
+        close: char,
+        around: bool,
+    },
+    Quote {
+        quote: char,
+        around: bool,
+    },
+}
+
+impl TextObject {
+    // the object for the key typed after `i` or `a`
+    pub fn from_key(c: char, around: bool) -> Option<Self> {
+        let pair = |open, close| TextObject::Pair {
+            open,
+            close,
+            around,
+        };
+
+        Some(match c {
+            'w' => TextObject::Word { around },
+            '(' | ')' | 'b' => pair('(', ')'),
+            '{' | '}' | 'B' => pair('{', '}'),
+            '[' | ']' => pair('[', ']'),
+            '<' | '>' => pair('<', '>'),
+            '"' | '\'' | '`' => TextObject::Quote { quote: c, around },
+            _ => return None,
+        })
+    }
+}
+
+// the single character motions: `f`, `F`, `t` and `T`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FindKind {
+    Forward,
+    Backward,
+    TillForward,
+    TillBackward,
+}
+
+impl FindKind {
+    // the same motion in the other direction, for `,`
+    pub fn reversed(self) -> Self {
+        match self {
+            FindKind::Forward => FindKind::Backward,
+            FindKind::Backward => FindKind::Forward,
+            FindKind::TillForward => FindKind::TillBackward,
+            FindKind::TillBackward => FindKind::TillForward,
+        }
+    }
+}
+
+// the column a find motion for `target` lands on from `col`. Till motions look past a match
+// right next to the cursor, so repeating them keeps making progress
+pub fn find_char(line: &str, col: usize, target: char, kind: FindKind) -> Option<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let col = col.min(chars.len());
+
+    match kind {
+        FindKind::Forward => (col + 1..chars.len()).find(|i| chars[*i] == target),
+        FindKind::TillForward => (col + 2..chars.len())
+            .find(|i| chars[*i] == target)
+            .map(|i| i - 1),
+        FindKind::Backward => (0..col).rev().find(|i| chars[*i] == target),
+        FindKind::TillBackward => (0..col.saturating_sub(1))
+            .rev()
+            .find(|i| chars[*i] == target)
+            .map(|i| i + 1),
+    }
+}
+
+// which characters make up a word, as configured by `iskeyword`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordChars {
+    spec: String,
+    alphabetic: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl KeywordChars {
+    // parses a spec like `@,48-57,_,-`: `@` is any letter, numbers are character codes, and
+    // `a-b` is an inclusive range of either
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut alphabetic = false;
+        let mut ranges = vec![];
+
+        for part in spec.split(',') {
+            if part == "@" {
+                alphabetic = true;
+                continue;
+            }
+
+            let range = match part.split_once('-') {
+                Some((start, end)) if !start.is_empty() && !end.is_empty() => {
+                    (parse_keyword_char(start)?, parse_keyword_char(end)?)
+                }
+                _ => {
+                    let c = parse_keyword_char(part)?;
+                    (c, c)
+                }
+            };
+            if range.0 > range.1 {
+                anyhow::bail!("Invalid range: {part}");
+            }
+            ranges.push(range);
+        }
+
+        Ok(Self {
+            spec: spec.to_string(),
+            alphabetic,
+            ranges,
+        })
+    }
+
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        (self.alphabetic && c.is_alphabetic())
+            || self
+                .ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&c))
+    }
+}
+
+impl Default for KeywordChars {
+    fn default() -> Self {
+        Self::parse("@,48-57,_").expect("default iskeyword is valid")
+    }
+}
+
+fn parse_keyword_char(part: &str) -> anyhow::Result<char> {
+    let mut chars = part.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => part
+            .parse::<u32>()
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| anyhow::anyhow!("Invalid argument: {part}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        assert_eq!(display_width("a日b", 8), 4);
+        assert_eq!(display_col("a日b", 2, 8), 3);
+        // either cell of the wide character is that character
+        assert_eq!(logical_col("a日b", 2, 8), 1);
+        assert_eq!(logical_col("a日b", 3, 8), 2);
+    }
+
+    #[test]
+    fn next_word_start_stops_at_punctuation_and_skips_blanks() {
+        let words = KeywordChars::default();
+
+        assert_eq!(next_word_start("foo.bar baz", 0, &words), Some(3));
+        assert_eq!(next_word_start("foo.bar baz", 3, &words), Some(4));
+        assert_eq!(next_word_start("foo.bar baz", 4, &words), Some(8));
+        assert_eq!(next_word_start("foo.bar baz", 8, &words), None);
+        assert_eq!(next_word_start("  foo", 0, &words), Some(2));
+    }
+
+    #[test]
+    fn prev_word_start_skips_blanks_then_the_word_before() {
+        let words = KeywordChars::default();
+
+        assert_eq!(prev_word_start("foo.bar  baz", 9, &words), Some(4));
+        assert_eq!(prev_word_start("foo.bar  baz", 11, &words), Some(9));
+        assert_eq!(prev_word_start("foo.bar  baz", 4, &words), Some(3));
+        assert_eq!(prev_word_start("  foo", 2, &words), None);
+        assert_eq!(prev_word_start("foo bar", usize::MAX, &words), Some(4));
+    }
+
+    #[test]
+    fn word_end_finds_the_last_character_of_a_word() {
+        let words = KeywordChars::default();
+
+        assert_eq!(word_end("foo.bar", 0, &words), Some(2));
+        assert_eq!(word_end("foo.bar", 3, &words), Some(3));
+        assert_eq!(word_end("foo  bar", 3, &words), Some(7));
+        assert_eq!(word_end("foo   ", 3, &words), None);
+    }
+
+    #[test]
+    fn word_object_takes_blanks_after_or_else_before() {
+        let words = KeywordChars::default();
+
+        assert_eq!(word_object("foo bar  baz", 5, false, &words), Some((4, 7)));
+        assert_eq!(word_object("foo bar  baz", 5, true, &words), Some((4, 9)));
+        // the last word has no blanks after it, so `aw` takes the ones before
+        assert_eq!(word_object("foo bar  baz", 10, true, &words), Some((7, 12)));
+        // on blanks, `iw` is the blanks and `aw` adds the word after them
+        assert_eq!(word_object("foo bar  baz", 7, false, &words), Some((7, 9)));
+        assert_eq!(word_object("foo bar  baz", 7, true, &words), Some((7, 12)));
+        assert_eq!(word_object("foo.bar", 3, false, &words), Some((3, 4)));
+        assert_eq!(word_object("foo", 3, false, &words), None);
+    }
+
+    #[test]
+    fn quote_object_finds_the_first_string_ending_after_the_cursor() {
+        let line = r#"say "hi there" now"#;
+
+        assert_eq!(quote_object(line, 6, '"', false), Some((5, 13)));
+        assert_eq!(quote_object(line, 6, '"', true), Some((4, 15)));
+        assert_eq!(quote_object(line, 0, '"', false), Some((5, 13)));
+        assert_eq!(quote_object(line, 15, '"', false), None);
+        // escaped quotes don't end the string
+        assert_eq!(quote_object(r#"x "a\"b" y"#, 3, '"', false), Some((3, 7)));
+        // with nothing after the closing quote, `a"` takes the blanks before it
+        assert_eq!(quote_object(r#"x "a""#, 3, '"', true), Some((1, 5)));
+    }
+}
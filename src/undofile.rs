@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::buffer::Edit;
+
+const HEADER: &str = "red undo 1";
+
+// the undo history of `file` lives in a `.editorundo` directory beside it, named after a hash of
+// the file name
+fn path(file: &str) -> PathBuf {
+    let file = Path::new(file);
+    let name = file.file_name().and_then(|n| n.to_str()).unwrap_or(".");
+    let dir = file.parent().unwrap_or(Path::new(""));
+
+    dir.join(".editorundo")
+        .join(format!("{:016x}", hash([name])))
+}
+
+// FNV-1a, so the hash stays the same across builds
+fn hash<'a>(lines: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for line in lines {
+        for byte in line.bytes().chain([b'\n']) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+// writes the undo groups for `file`, tagged with a hash of its contents so a stale history is
+// never applied to a file that changed elsewhere
+pub fn save(file: &str, lines: &[String], groups: &[Vec<Edit>]) -> anyhow::Result<()> {
+    let mut out = format!(
+        "{HEADER}\n{:016x}\n",
+        hash(lines.iter().map(|l| l.as_str()))
+    );
+    for group in groups {
+        out.push_str(&format!("group {}\n", group.len()));
+        for edit in group {
+            out.push_str(&format!(
+                "edit {} {} {}\n",
+                edit.start,
+                edit.end,
+                edit.lines.len()
+            ));
+            for line in &edit.lines {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+
+    let path = path(file);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Can't create undo directory")?;
+    }
+    fs::write(&path, out).with_context(|| format!("Can't write undo file {}", path.display()))
+}
+
+// the undo groups saved for `file`, or `None` when there are none, they're unreadable, or they
+// were saved for different contents
+pub fn load(file: &str, lines: &[String]) -> Option<Vec<Vec<Edit>>> {
+    let contents = fs::read_to_string(path(file)).ok()?;
+    let mut rows = contents.split('\n');
+
+    if rows.next()? != HEADER {
+        return None;
+    }
+    let expected = format!("{:016x}", hash(lines.iter().map(|l| l.as_str())));
+    if rows.next()? != expected {
+        return None;
+    }
+
+    let mut groups = vec![];
+    while let Some(row) = rows.next().filter(|row| !row.is_empty()) {
+        let edits = row.strip_prefix("group ")?.parse().ok()?;
+        let mut group = Vec::with_capacity(edits);
+        for _ in 0..edits {
+            let mut fields = rows.next()?.strip_prefix("edit ")?.split(' ');
+            let mut number = || fields.next()?.parse::<usize>().ok();
+            let (start, end, count) = (number()?, number()?, number()?);
+            let lines = (0..count)
+                .map(|_| rows.next().map(|l| l.to_string()))
+                .collect::<Option<Vec<_>>>()?;
+            group.push(Edit::replace(start, end, lines));
+        }
+        groups.push(group);
+    }
+
+    Some(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn history_loads_back_only_for_the_same_contents() {
+        let dir = std::env::temp_dir().join(format!("red-{}-undofile", std::process::id()));
+        let file = dir.join("a.txt").display().to_string();
+        let contents = lines(&["one", "two"]);
+        let groups = vec![
+            vec![Edit::replace(0, 1, lines(&["", "x"]))],
+            vec![
+                Edit::replace(1, 1, vec![]),
+                Edit::replace(0, 2, lines(&["y"])),
+            ],
+        ];
+
+        save(&file, &contents, &groups).unwrap();
+        let loaded = load(&file, &contents).unwrap();
+        let flat = |groups: &[Vec<Edit>]| -> Vec<Vec<(usize, usize, Vec<String>)>> {
+            groups
+                .iter()
+                .map(|group| {
+                    group
+                        .iter()
+                        .map(|edit| (edit.start, edit.end, edit.lines.clone()))
+                        .collect()
+                })
+                .collect()
+        };
+        assert_eq!(flat(&loaded), flat(&groups));
+
+        // a file changed since the history was saved gets none of it
+        assert!(load(&file, &lines(&["one", "three"])).is_none());
+
+        fs::write(path(&file), "red undo 1\nnonsense\n").unwrap();
+        assert!(load(&file, &contents).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}